@@ -110,3 +110,16 @@ pub struct BackupKeys {
     #[napi(getter)]
     pub backup_version: Option<String>,
 }
+
+/// The backup key stored in the machine, along with the backup version
+/// it is used for.
+#[napi]
+#[derive(Debug)]
+pub struct BackupKeyVersion {
+    /// The version of the backup.
+    #[napi(readonly)]
+    pub version: String,
+    /// The public part of the backup key, encoded as base64.
+    #[napi(readonly)]
+    pub public_key: String,
+}
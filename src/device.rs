@@ -0,0 +1,324 @@
+//! Types related to devices.
+
+use std::collections::BTreeMap;
+
+use matrix_sdk_common::ruma::{
+    api::client::keys::upload_signatures::{self, v3::SignedKeys},
+    serde::Raw,
+    OwnedUserId, TransactionId,
+};
+use napi_derive::*;
+
+use crate::{identifiers, into_err, requests, verification, vodozemac};
+
+/// The local trust state of a device.
+#[napi]
+#[derive(Debug)]
+pub enum LocalTrust {
+    /// The device has been verified and is trusted.
+    Verified = 0,
+
+    /// The device been blacklisted from communicating.
+    BlackListed = 1,
+
+    /// The trust state of the device is being ignored.
+    Ignored = 2,
+
+    /// The trust state is unset.
+    Unset = 3,
+}
+
+impl From<matrix_sdk_crypto::LocalTrust> for LocalTrust {
+    fn from(value: matrix_sdk_crypto::LocalTrust) -> Self {
+        use matrix_sdk_crypto::LocalTrust::*;
+
+        match value {
+            Verified => Self::Verified,
+            BlackListed => Self::BlackListed,
+            Ignored => Self::Ignored,
+            Unset => Self::Unset,
+        }
+    }
+}
+
+impl From<LocalTrust> for matrix_sdk_crypto::LocalTrust {
+    fn from(value: LocalTrust) -> Self {
+        use LocalTrust::*;
+
+        match value {
+            Verified => Self::Verified,
+            BlackListed => Self::BlackListed,
+            Ignored => Self::Ignored,
+            Unset => Self::Unset,
+        }
+    }
+}
+
+/// A device represents a E2EE capable client of an user.
+#[napi]
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub(crate) inner: matrix_sdk_crypto::Device,
+}
+
+impl From<matrix_sdk_crypto::Device> for Device {
+    fn from(inner: matrix_sdk_crypto::Device) -> Self {
+        Self { inner }
+    }
+}
+
+#[napi]
+impl Device {
+    /// The user ID of the device owner.
+    #[napi(getter)]
+    pub fn user_id(&self) -> identifiers::UserId {
+        self.inner.user_id().to_owned().into()
+    }
+
+    /// The unique ID of the device.
+    #[napi(getter)]
+    pub fn device_id(&self) -> identifiers::DeviceId {
+        self.inner.device_id().to_owned().into()
+    }
+
+    /// Get the human readable name of the device.
+    #[napi(getter)]
+    pub fn display_name(&self) -> Option<String> {
+        self.inner.display_name().map(ToOwned::to_owned)
+    }
+
+    /// Get the Curve25519 key of the given device.
+    #[napi(getter)]
+    pub fn curve25519_key(&self) -> Option<vodozemac::Curve25519PublicKey> {
+        self.inner.curve25519_key().map(Into::into)
+    }
+
+    /// Get the Ed25519 key of the given device.
+    #[napi(getter)]
+    pub fn ed25519_key(&self) -> Option<vodozemac::Ed25519PublicKey> {
+        self.inner.ed25519_key().map(Into::into)
+    }
+
+    /// Get the JSON-encoded map of the keys of the device, from their
+    /// key ID, e.g. `ed25519:DEVICEID`, to their unpadded base64
+    /// encoding.
+    #[napi(getter)]
+    pub fn keys(&self) -> napi::Result<String> {
+        serde_json::to_string(
+            &self
+                .inner
+                .keys()
+                .iter()
+                .map(|(key_id, key)| (key_id.to_string(), key.to_base64()))
+                .collect::<BTreeMap<_, _>>(),
+        )
+        .map_err(into_err)
+    }
+
+    /// Get the list of algorithms this device supports.
+    #[napi(getter)]
+    pub fn algorithms(&self) -> Vec<String> {
+        self.inner.algorithms().iter().map(ToString::to_string).collect()
+    }
+
+    /// Get the trust state of the device.
+    #[napi(getter)]
+    pub fn local_trust_state(&self) -> LocalTrust {
+        self.inner.local_trust_state().into()
+    }
+
+    /// Is the device locally marked as trusted?
+    #[napi]
+    pub fn is_locally_trusted(&self) -> bool {
+        self.inner.is_locally_trusted()
+    }
+
+    /// Is the device locally marked as blacklisted?
+    ///
+    /// Blacklisted devices won't receive any room keys when we share
+    /// them.
+    #[napi(getter)]
+    pub fn is_blacklisted(&self) -> bool {
+        self.inner.is_blacklisted()
+    }
+
+    /// Mark the device as blacklisted, and persist this trust state in
+    /// the store.
+    #[napi]
+    pub async fn blacklist(&self) -> napi::Result<()> {
+        self.inner
+            .set_local_trust(matrix_sdk_crypto::LocalTrust::BlackListed)
+            .await
+            .map_err(into_err)
+    }
+
+    /// Is this device considered to be verified.
+    ///
+    /// This method returns true if either the `is_locally_trusted`
+    /// method returns `true` or if the `is_cross_signing_trusted`
+    /// method returns `true`.
+    #[napi]
+    pub fn is_verified(&self) -> bool {
+        self.inner.is_verified()
+    }
+
+    /// Is this device considered to be verified using cross signing.
+    #[napi]
+    pub fn is_cross_signing_trusted(&self) -> bool {
+        self.inner.is_cross_signing_trusted()
+    }
+
+    /// Is this device cross-signed by its owner?
+    #[napi]
+    pub fn is_cross_signed_by_owner(&self) -> bool {
+        self.inner.is_cross_signed_by_owner()
+    }
+
+    /// Sign this device with our self-signing key, marking it as
+    /// verified for the other devices of our user.
+    ///
+    /// This only works for our own devices, and requires the private
+    /// part of the self-signing key. The returned
+    /// `SignatureUploadRequest` needs to be sent out.
+    #[napi]
+    pub async fn sign_device(&self) -> napi::Result<requests::SignatureUploadRequest> {
+        let request = self.inner.verify().await.map_err(into_err)?;
+
+        requests::SignatureUploadRequest::try_from((TransactionId::new().to_string(), &request))
+    }
+
+    /// Request an interactive verification with this device.
+    ///
+    /// Returns the created `VerificationRequest` and the to-device
+    /// request that needs to be sent out.
+    ///
+    /// # Arguments
+    ///
+    /// * `methods`, the verification methods that we want to support;
+    ///   if not set, all the methods supported by the SDK are advertised.
+    #[napi]
+    pub async fn request_verification(
+        &self,
+        methods: Option<Vec<verification::VerificationMethod>>,
+    ) -> napi::Result<verification::RequestedVerification> {
+        let (request, outgoing_request) = match methods {
+            Some(methods) => self
+                .inner
+                .request_verification_with_methods(methods.into_iter().map(Into::into).collect()),
+            None => self.inner.request_verification(),
+        };
+
+        Ok(verification::RequestedVerification {
+            request: request.into(),
+            outgoing_request: requests::OutgoingVerificationRequest(outgoing_request).try_into()?,
+        })
+    }
+
+    /// Start an interactive verification with this device, using the
+    /// SAS method directly, i.e. without a `VerificationRequest`.
+    ///
+    /// Returns the started `Sas` and the to-device request that needs
+    /// to be sent out.
+    #[napi]
+    pub async fn start_verification(&self) -> napi::Result<verification::StartedSas> {
+        let (sas, request) = self.inner.start_verification().await.map_err(into_err)?;
+
+        Ok(verification::StartedSas {
+            sas: sas.into(),
+            outgoing_request: requests::ToDeviceRequest::try_from((
+                request.txn_id.to_string(),
+                &request,
+            ))?,
+        })
+    }
+}
+
+/// A read only view over all devices belonging to a user.
+#[napi]
+#[derive(Debug)]
+pub struct UserDevices {
+    pub(crate) inner: matrix_sdk_crypto::UserDevices,
+}
+
+impl From<matrix_sdk_crypto::UserDevices> for UserDevices {
+    fn from(inner: matrix_sdk_crypto::UserDevices) -> Self {
+        Self { inner }
+    }
+}
+
+#[napi]
+impl UserDevices {
+    /// Get the specific device with the given device ID.
+    #[napi(strict)]
+    pub fn get(&self, device_id: &identifiers::DeviceId) -> Option<Device> {
+        self.inner.get(device_id.inner.as_ref()).map(Into::into)
+    }
+
+    /// Returns true if there is at least one device of this user that
+    /// is considered to be verified, false otherwise.
+    #[napi]
+    pub fn is_any_verified(&self) -> bool {
+        self.inner.is_any_verified()
+    }
+
+    /// Returns true if there is at least one device of this user that
+    /// is not considered to be verified, false otherwise.
+    #[napi]
+    pub fn is_any_unverified(&self) -> bool {
+        self.inner.devices().any(|device| !device.is_verified())
+    }
+
+    /// Get all the devices of the user.
+    #[napi]
+    pub fn devices(&self) -> Vec<Device> {
+        self.inner.devices().map(Into::into).collect()
+    }
+
+    /// Get the devices of the user that are not verified.
+    #[napi]
+    pub fn unverified_devices(&self) -> Vec<Device> {
+        self.inner.devices().filter(|device| !device.is_verified()).map(Into::into).collect()
+    }
+
+    /// Get the devices of the user that are locally marked as
+    /// blacklisted.
+    #[napi]
+    pub fn blacklisted_devices(&self) -> Vec<Device> {
+        self.inner.devices().filter(|device| device.is_blacklisted()).map(Into::into).collect()
+    }
+
+    /// Sign all the unverified devices of the user with our
+    /// self-signing key.
+    ///
+    /// This only works for our own devices, and requires the private
+    /// part of the self-signing key. Returns a single
+    /// `SignatureUploadRequest` covering all the signed devices that
+    /// needs to be sent out, or `null` if all the devices are already
+    /// verified.
+    #[napi]
+    pub async fn verify_all(&self) -> napi::Result<Option<requests::SignatureUploadRequest>> {
+        let unverified_devices =
+            self.inner.devices().filter(|device| !device.is_verified()).collect::<Vec<_>>();
+        let mut signed_keys = BTreeMap::<OwnedUserId, SignedKeys>::new();
+
+        for device in unverified_devices {
+            for (user_id, keys) in device.verify().await.map_err(into_err)?.signed_keys {
+                let user_signed_keys = signed_keys.entry(user_id).or_default();
+
+                for (device_id, device_keys) in &keys {
+                    user_signed_keys
+                        .add_device_keys(device_id.into(), Raw::from_json(device_keys.to_owned()));
+                }
+            }
+        }
+
+        if signed_keys.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(requests::SignatureUploadRequest::try_from((
+            TransactionId::new().to_string(),
+            &upload_signatures::v3::Request::new(signed_keys),
+        ))?))
+    }
+}
@@ -59,3 +59,29 @@ impl From<RumaHistoryVisibility> for HistoryVisibility {
         }
     }
 }
+
+/// An event from a room timeline to decrypt with
+/// `OlmMachine.batch_decrypt`.
+#[napi(object)]
+#[derive(Debug)]
+pub struct RoomEventToDecrypt {
+    /// The JSON-encoded event that should be decrypted.
+    pub event: String,
+
+    /// The ID of the room where the event was sent to.
+    pub room_id: String,
+}
+
+/// An event for a room to encrypt with `OlmMachine.batch_encrypt`.
+#[napi(object)]
+#[derive(Debug)]
+pub struct RoomEventToEncrypt {
+    /// The ID of the room for which the event should be encrypted.
+    pub room_id: String,
+
+    /// The plaintext type of the event.
+    pub event_type: String,
+
+    /// The JSON-encoded content of the event.
+    pub content: String,
+}
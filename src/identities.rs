@@ -0,0 +1,247 @@
+//! User identities.
+
+use matrix_sdk_common::ruma::TransactionId;
+use matrix_sdk_crypto::{
+    types::{MasterPubkey, SelfSigningPubkey},
+    UserIdentityData,
+};
+use napi_derive::*;
+
+use crate::{identifiers, into_err, requests};
+
+/// The decision taken when the identity of a user has changed, see
+/// `OlmMachine.resolve_identity_conflict`.
+#[napi]
+#[derive(Debug)]
+pub enum IdentityConflictAction {
+    /// Accept and pin the new identity.
+    PinNew = 0,
+
+    /// Treat the new identity as a compromise, and blacklist all the
+    /// devices of the user so that no room key is shared with them.
+    Reject = 1,
+
+    /// Don't do anything, the conflict will be reported again.
+    Ignore = 2,
+}
+
+/// Struct representing a cross signing identity of a user.
+///
+/// This is the user identity of a user that is our own.
+#[napi]
+#[derive(Debug, Clone)]
+pub struct OwnUserIdentity {
+    pub(crate) inner: matrix_sdk_crypto::OwnUserIdentity,
+
+    /// The machine the identity has been obtained from, used to look
+    /// devices up.
+    pub(crate) machine: matrix_sdk_crypto::OlmMachine,
+}
+
+#[napi]
+impl OwnUserIdentity {
+    /// The unique ID of the user this identity belongs to.
+    #[napi(getter)]
+    pub fn user_id(&self) -> identifiers::UserId {
+        self.inner.user_id().to_owned().into()
+    }
+
+    /// Get the JSON-encoded public part of the master key of this
+    /// identity.
+    #[napi(getter)]
+    pub fn master_key(&self) -> napi::Result<String> {
+        serde_json::to_string(self.inner.master_key()).map_err(into_err)
+    }
+
+    /// Get the JSON-encoded public part of the self-signing key of
+    /// this identity.
+    #[napi(getter)]
+    pub fn self_signing_key(&self) -> napi::Result<String> {
+        serde_json::to_string(self.inner.self_signing_key()).map_err(into_err)
+    }
+
+    /// Get the JSON-encoded public part of the user-signing key of
+    /// this identity.
+    #[napi(getter)]
+    pub fn user_signing_key(&self) -> napi::Result<String> {
+        serde_json::to_string(self.inner.user_signing_key()).map_err(into_err)
+    }
+
+    /// Is this user identity verified.
+    #[napi]
+    pub fn is_verified(&self) -> bool {
+        self.inner.is_verified()
+    }
+
+    /// Mark our user identity as verified, and sign its master key
+    /// with our own device.
+    ///
+    /// The returned `SignatureUploadRequest` needs to be sent out.
+    #[napi]
+    pub async fn verify(&self) -> napi::Result<requests::SignatureUploadRequest> {
+        let request = self.inner.verify().await.map_err(into_err)?;
+
+        requests::SignatureUploadRequest::try_from((TransactionId::new().to_string(), &request))
+    }
+
+    /// Does our user identity trust our own device, i.e. have we signed
+    /// our own device keys with our self-signing key.
+    #[napi]
+    pub async fn trusts_our_own_device(&self) -> napi::Result<bool> {
+        self.inner.trusts_our_own_device().await.map_err(into_err)
+    }
+
+    /// Sign one of our own devices with our self-signing key.
+    ///
+    /// This requires the private part of the self-signing key. The
+    /// returned `SignatureUploadRequest` needs to be sent out. Returns
+    /// `null` if the device is unknown.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the unique ID of the device owner; it must be our
+    ///   own user ID.
+    /// * `device_id`, the unique ID of the device to sign.
+    #[napi(strict)]
+    pub async fn sign_device(
+        &self,
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+    ) -> napi::Result<Option<requests::SignatureUploadRequest>> {
+        let user_id = user_id.inner.clone();
+        let device_id = device_id.inner.clone();
+
+        if user_id != self.inner.user_id() {
+            return Err(napi::Error::from_reason(format!(
+                "The device of `{user_id}` cannot be signed with the identity of `{}`",
+                self.inner.user_id()
+            )));
+        }
+
+        let Some(device) =
+            self.machine.get_device(&user_id, &device_id, None).await.map_err(into_err)?
+        else {
+            return Ok(None);
+        };
+
+        let request = device.verify().await.map_err(into_err)?;
+
+        Ok(Some(requests::SignatureUploadRequest::try_from((
+            TransactionId::new().to_string(),
+            &request,
+        ))?))
+    }
+}
+
+/// Struct representing a cross signing identity of a user.
+///
+/// This is the user identity of a user that isn't our own.
+#[napi]
+#[derive(Debug, Clone)]
+pub struct UserIdentity {
+    pub(crate) inner: matrix_sdk_crypto::OtherUserIdentity,
+}
+
+impl From<matrix_sdk_crypto::OtherUserIdentity> for UserIdentity {
+    fn from(inner: matrix_sdk_crypto::OtherUserIdentity) -> Self {
+        Self { inner }
+    }
+}
+
+#[napi]
+impl UserIdentity {
+    /// The unique ID of the user this identity belongs to.
+    #[napi(getter)]
+    pub fn user_id(&self) -> identifiers::UserId {
+        self.inner.user_id().to_owned().into()
+    }
+
+    /// Get the JSON-encoded public part of the master key of this
+    /// identity.
+    #[napi(getter)]
+    pub fn master_key(&self) -> napi::Result<String> {
+        serde_json::to_string(self.inner.master_key()).map_err(into_err)
+    }
+
+    /// Get the JSON-encoded public part of the self-signing key of
+    /// this identity.
+    #[napi(getter)]
+    pub fn self_signing_key(&self) -> napi::Result<String> {
+        serde_json::to_string(self.inner.self_signing_key()).map_err(into_err)
+    }
+
+    /// Is this user identity verified.
+    #[napi]
+    pub fn is_verified(&self) -> bool {
+        self.inner.is_verified()
+    }
+
+    /// Verify this identity, by signing its master key with our
+    /// user-signing key.
+    ///
+    /// This requires the private part of the user-signing key. The
+    /// returned `SignatureUploadRequest` needs to be sent out.
+    #[napi]
+    pub async fn verify(&self) -> napi::Result<requests::SignatureUploadRequest> {
+        let request = self.inner.verify().await.map_err(into_err)?;
+
+        requests::SignatureUploadRequest::try_from((TransactionId::new().to_string(), &request))
+    }
+
+    /// Has the identity changed in a way that requires approval from
+    /// the user?
+    ///
+    /// This is the case if the identity changed after we had pinned a
+    /// different one, unless the new identity is verified.
+    #[napi]
+    pub fn identity_needs_user_approval(&self) -> bool {
+        self.inner.identity_needs_user_approval()
+    }
+
+    /// Pin the current identity, i.e. the public part of its master
+    /// key, so that it doesn't need to be approved anymore.
+    #[napi]
+    pub async fn pin_current_identity(&self) -> napi::Result<()> {
+        self.inner.pin_current_master_key().await.map_err(into_err)
+    }
+}
+
+/// The public cross-signing keys of a user identity, as reported by
+/// `OlmMachine.on_identity_change`.
+#[napi]
+#[derive(Debug, Clone)]
+pub struct CrossSigningPublicKeys {
+    master_key: MasterPubkey,
+    self_signing_key: SelfSigningPubkey,
+}
+
+impl CrossSigningPublicKeys {
+    /// Whether both keys have the same master key.
+    pub(crate) fn has_same_master_key(&self, other: &Self) -> bool {
+        self.master_key.get_first_key() == other.master_key.get_first_key()
+    }
+}
+
+impl From<&UserIdentityData> for CrossSigningPublicKeys {
+    fn from(identity: &UserIdentityData) -> Self {
+        Self {
+            master_key: identity.master_key().to_owned(),
+            self_signing_key: identity.self_signing_key().to_owned(),
+        }
+    }
+}
+
+#[napi]
+impl CrossSigningPublicKeys {
+    /// Get the JSON-encoded public part of the master key.
+    #[napi(getter)]
+    pub fn master_key(&self) -> napi::Result<String> {
+        serde_json::to_string(&self.master_key).map_err(into_err)
+    }
+
+    /// Get the JSON-encoded public part of the self-signing key.
+    #[napi(getter)]
+    pub fn self_signing_key(&self) -> napi::Result<String> {
+        serde_json::to_string(&self.self_signing_key).map_err(into_err)
+    }
+}
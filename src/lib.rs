@@ -20,10 +20,12 @@ use napi_derive::napi;
 
 pub mod attachment;
 pub mod backup;
+pub mod device;
 pub mod encryption;
 mod errors;
 pub mod events;
 pub mod identifiers;
+pub mod identities;
 pub mod machine;
 pub mod olm;
 pub mod requests;
@@ -32,6 +34,7 @@ pub mod sync_events;
 #[cfg(feature = "tracing")]
 pub mod tracing;
 pub mod types;
+pub mod verification;
 pub mod vodozemac;
 
 /// Object containing the versions of the Rust libraries we are using.
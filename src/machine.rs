@@ -1,31 +1,77 @@
 //! The crypto specific Olm objects.
 
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     mem::ManuallyDrop,
     ops::Deref,
-    sync::Arc,
+    path::Path,
+    pin::pin,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
 };
 
-use matrix_sdk_common::ruma::{serde::Raw, OneTimeKeyAlgorithm, OwnedTransactionId, UInt};
+use futures_util::{future, stream, Stream, StreamExt};
+use matrix_sdk_common::{
+    ruma::{
+        api::client::{backup::RoomKeyBackup, keys::upload_keys},
+        events::{
+            room::member::{MembershipState, SyncRoomMemberEvent},
+            AnyToDeviceEvent,
+        },
+        serde::Raw,
+        OneTimeKeyAlgorithm, OwnedDeviceId, OwnedEventId, OwnedRoomId, OwnedTransactionId,
+        OwnedUserId, RoomId, TransactionId, UInt,
+    },
+    store_locks::CrossProcessStoreLockGuard,
+};
+#[cfg(feature = "qrcode")]
+use matrix_sdk_crypto::matrix_sdk_qrcode::QrVerificationData;
 use matrix_sdk_crypto::{
-    backups::MegolmV1BackupKey, types::RoomKeyBackupInfo, DecryptionSettings,
-    EncryptionSyncChanges, TrustRequirement,
+    backups::MegolmV1BackupKey,
+    olm::{ExportedRoomKey, PickledSession, Session, ShareInfo},
+    store::{Changes, CryptoStore},
+    types::{
+        events::room::encrypted::EncryptedEvent, requests::AnyOutgoingRequest, MegolmV1AuthData,
+        RoomKeyBackupInfo,
+    },
+    vodozemac::{base64_decode, base64_encode, Curve25519PublicKey, Ed25519PublicKey},
+    DecryptionSettings, DeviceData, EncryptionSyncChanges, TrustRequirement,
+};
+use matrix_sdk_store_encryption::StoreCipher;
+#[cfg(feature = "qrcode")]
+use napi::bindgen_prelude::Uint8Array;
+use napi::{
+    bindgen_prelude::{
+        spawn, within_runtime_if_available, BigInt, Either, Either3, Either6, ToNapiValue,
+    },
+    threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode},
+    Env, JsFunction,
 };
-use napi::bindgen_prelude::{within_runtime_if_available, Either6};
 use napi_derive::*;
 use serde_json::value::RawValue;
-use zeroize::Zeroize;
+use zeroize::{Zeroize, Zeroizing};
 
 use crate::{
-    backup::{BackupDecryptionKey, BackupKeys, RoomKeyCounts},
-    encryption, identifiers, into_err, olm, requests, responses,
+    backup::{BackupDecryptionKey, BackupKeyVersion, BackupKeys, RoomKeyCounts},
+    device, encryption, events, identifiers, identities, into_err, olm, requests, responses,
     responses::response_from_string,
     sync_events,
     types::{self, SignatureVerification},
-    vodozemac,
+    verification, vodozemac,
 };
 
+/// The key of the cross-process lock taken by
+/// `OlmMachine.lock_for_current_thread`.
+const STORE_LOCK_KEY: &str = "olm_machine_lock";
+
+/// Namespace the keys of `OlmMachine.set_custom_account_data` in the
+/// custom values of the store.
+fn custom_account_data_key(key: &str) -> String {
+    format!("custom_account_data:{key}")
+}
+
 /// The value used by the `OlmMachine` JS class.
 ///
 /// It has 2 states: `Opened` and `Closed`. Why maintaining the state here?
@@ -72,12 +118,52 @@ pub enum StoreType {
     Sqlite,
 }
 
+/// An exclusive lock on the store of an `OlmMachine`, obtained with
+/// `OlmMachine.lock_for_current_thread`.
+///
+/// The lock is held until `release` is called, or until the object is
+/// garbage collected.
+#[napi]
+pub struct OlmMachineLock {
+    guard: Option<CrossProcessStoreLockGuard>,
+}
+
+#[napi]
+impl OlmMachineLock {
+    /// Release the lock. Calling this method more than once has no
+    /// effect.
+    #[napi]
+    pub fn release(&mut self) {
+        self.guard = None;
+    }
+}
+
 /// State machine implementation of the Olm/Megolm encryption protocol
 /// used for Matrix end to end encryption.
 // #[napi(custom_finalize)]
 #[napi]
 pub struct OlmMachine {
     inner: OlmMachineInner,
+    temporary_secrets: Mutex<HashMap<String, Zeroizing<String>>>,
+
+    /// The number of to-device events decrypted with Olm.
+    olm_messages_received: AtomicU32,
+
+    /// The number of room events decrypted with Megolm.
+    megolm_messages_received: AtomicU32,
+
+    /// The number of to-device requests that have been sent out.
+    to_device_messages_sent: AtomicU32,
+
+    /// The number of room events that have been encrypted or sent out.
+    room_messages_sent: AtomicU32,
+
+    /// The users whose identity has changed, see
+    /// `get_identity_changed_users`.
+    identity_changed_users: Arc<Mutex<BTreeSet<OwnedUserId>>>,
+
+    /// The users who have new devices, see `new_device_users`.
+    new_device_users: Arc<Mutex<BTreeSet<OwnedUserId>>>,
 }
 
 #[napi]
@@ -124,34 +210,55 @@ impl OlmMachine {
         let user_id = user_id.as_ref();
         let device_id = device_id.as_ref();
 
-        Ok(OlmMachine {
-            inner: OlmMachineInner::Opened(ManuallyDrop::new(match store_path {
-                Some(store_path) => {
-                    let machine = match store_type.unwrap_or_default() {
-                        StoreType::Sqlite => {
-                            matrix_sdk_crypto::OlmMachine::with_store(
-                                user_id,
-                                device_id,
-                                matrix_sdk_sqlite::SqliteCryptoStore::open(
-                                    store_path,
-                                    store_passphrase.as_deref(),
-                                )
-                                .await
-                                .map(Arc::new)
-                                .map_err(into_err)?,
-                                None,
+        let machine = match store_path {
+            Some(store_path) => {
+                let machine = match store_type.unwrap_or_default() {
+                    StoreType::Sqlite => {
+                        matrix_sdk_crypto::OlmMachine::with_store(
+                            user_id,
+                            device_id,
+                            matrix_sdk_sqlite::SqliteCryptoStore::open(
+                                store_path,
+                                store_passphrase.as_deref(),
                             )
                             .await
-                        }
-                    };
+                            .map(Arc::new)
+                            .map_err(into_err)?,
+                            None,
+                        )
+                        .await
+                    }
+                };
 
-                    store_passphrase.zeroize();
+                store_passphrase.zeroize();
 
-                    machine.map_err(into_err)?
-                }
+                machine.map_err(into_err)?
+            }
+
+            None => matrix_sdk_crypto::OlmMachine::new(user_id, device_id).await,
+        };
 
-                None => matrix_sdk_crypto::OlmMachine::new(user_id, device_id).await,
-            })),
+        let identity_changed_users = collect_user_ids(
+            machine.store().identities_stream_raw().flat_map(|(identities, _)| {
+                stream::iter(
+                    identities.changed.into_iter().map(|identity| identity.user_id().to_owned()),
+                )
+            }),
+        );
+        let new_device_users =
+            collect_user_ids(machine.store().identities_stream_raw().flat_map(|(_, devices)| {
+                stream::iter(devices.new.into_iter().map(|device| device.user_id().to_owned()))
+            }));
+
+        Ok(OlmMachine {
+            inner: OlmMachineInner::Opened(ManuallyDrop::new(machine)),
+            temporary_secrets: Default::default(),
+            olm_messages_received: Default::default(),
+            megolm_messages_received: Default::default(),
+            to_device_messages_sent: Default::default(),
+            room_messages_sent: Default::default(),
+            identity_changed_users,
+            new_device_users,
         })
     }
 
@@ -165,6 +272,32 @@ impl OlmMachine {
         ))
     }
 
+    /// Check whether the store at `store_path` contains an Olm
+    /// account, without building an `OlmMachine`.
+    ///
+    /// No store is created if none exists at `store_path`. The
+    /// `store_passphrase` must be the one the store has been created
+    /// with, if any.
+    #[napi(strict)]
+    pub async fn is_store_initialised(
+        store_path: String,
+        mut store_passphrase: Option<String>,
+    ) -> napi::Result<bool> {
+        if !Path::new(&store_path).join("matrix-sdk-crypto.sqlite3").exists() {
+            return Ok(false);
+        }
+
+        let store =
+            matrix_sdk_sqlite::SqliteCryptoStore::open(store_path, store_passphrase.as_deref())
+                .await;
+
+        store_passphrase.zeroize();
+
+        let account = store.map_err(into_err)?.load_account().await.map_err(into_err)?;
+
+        Ok(account.is_some())
+    }
+
     /// The unique user ID that owns this `OlmMachine` instance.
     #[napi(getter)]
     pub fn user_id(&self) -> identifiers::UserId {
@@ -183,6 +316,64 @@ impl OlmMachine {
         self.inner.identity_keys().into()
     }
 
+    /// Get the fingerprint of this device, i.e. its Ed25519 key
+    /// encoded in base64 and split into space-separated groups of 4
+    /// characters, as displayed to users.
+    #[napi]
+    pub async fn device_fingerprint(&self) -> String {
+        let key = self.inner.identity_keys().ed25519.to_base64();
+
+        key.as_bytes()
+            .chunks(4)
+            .map(|chunk| String::from_utf8_lossy(chunk))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Get diagnostic information about the Olm account of this
+    /// `OlmMachine`.
+    ///
+    /// This is mostly useful for device management UIs, or to
+    /// validate that the initial setup of a device went well.
+    #[napi]
+    pub async fn get_account_info(&self) -> napi::Result<olm::AccountInfo> {
+        let account =
+            self.inner.store().load_account().await.map_err(into_err)?.ok_or_else(|| {
+                napi::Error::from_reason("The Olm account is missing from the store".to_owned())
+            })?;
+
+        Ok((&account).into())
+    }
+
+    /// Get the number of Olm and Megolm messages this `OlmMachine` has
+    /// decrypted since it has been initialized.
+    ///
+    /// The counters aren't persisted, and are mostly useful for
+    /// telemetry about the crypto load.
+    #[napi]
+    pub async fn get_receive_count(&self) -> olm::ReceiveCount {
+        olm::ReceiveCount {
+            olm_messages: self.olm_messages_received.load(Ordering::Relaxed),
+            megolm_messages: self.megolm_messages_received.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Get the number of to-device and room messages this `OlmMachine`
+    /// has sent since it has been initialized.
+    ///
+    /// To-device messages are counted when their `ToDeviceRequest` is
+    /// marked as sent. Room messages are counted when they are
+    /// encrypted with `encrypt_room_event` or `batch_encrypt`, or when
+    /// their `RoomMessageRequest` is marked as sent. The counters aren't
+    /// persisted.
+    #[napi]
+    pub async fn get_send_count(&self) -> olm::SendCount {
+        olm::SendCount {
+            to_device_messages: self.to_device_messages_sent.load(Ordering::Relaxed),
+            room_messages: self.room_messages_sent.load(Ordering::Relaxed),
+        }
+    }
+
     /// Handle a to-device and one-time key counts from a sync response.
     ///
     /// This will decrypt and handle to-device events returning the
@@ -206,8 +397,9 @@ impl OlmMachine {
         one_time_key_counts: HashMap<String, u32>,
         unused_fallback_keys: Vec<String>,
     ) -> napi::Result<String> {
-        let to_device_events_decoded =
+        let to_device_events_decoded: Vec<Raw<AnyToDeviceEvent>> =
             serde_json::from_str(to_device_events.as_ref()).map_err(into_err)?;
+        let encrypted_events = encrypted_event_count(&to_device_events_decoded);
         let changed_devices = changed_devices.inner.clone();
         let one_time_key_counts = one_time_key_counts
             .iter()
@@ -220,22 +412,301 @@ impl OlmMachine {
                 .collect::<Vec<_>>(),
         );
 
-        serde_json::to_string(
-            &self
-                .inner
-                .receive_sync_changes(EncryptionSyncChanges {
-                    to_device_events: to_device_events_decoded,
-                    changed_devices: &changed_devices,
-                    one_time_keys_counts: &one_time_key_counts,
-                    unused_fallback_keys: unused_fallback_keys.as_deref(),
-
-                    // matrix-sdk-crypto does not (currently) use `next_batch_token`.
-                    next_batch_token: None,
-                })
-                .await
-                .map_err(into_err)?,
-        )
-        .map_err(into_err)
+        let changes = self
+            .inner
+            .receive_sync_changes(EncryptionSyncChanges {
+                to_device_events: to_device_events_decoded,
+                changed_devices: &changed_devices,
+                one_time_keys_counts: &one_time_key_counts,
+                unused_fallback_keys: unused_fallback_keys.as_deref(),
+
+                // matrix-sdk-crypto does not (currently) use `next_batch_token`.
+                next_batch_token: None,
+            })
+            .await
+            .map_err(into_err)?;
+
+        self.olm_messages_received
+            .fetch_add(encrypted_events - encrypted_event_count(&changes.0), Ordering::Relaxed);
+
+        serde_json::to_string(&changes).map_err(into_err)
+    }
+
+    /// Process a batch of to-device events.
+    ///
+    /// This is a narrower variant of `receive_sync_changes` that only
+    /// handles to-device events. Each event is handled independently:
+    /// an event that cannot be parsed or decrypted is reported in
+    /// `errors` instead of aborting the whole batch. All the pending
+    /// outgoing requests are returned too, as with `outgoing_requests`.
+    ///
+    /// # Arguments
+    ///
+    /// * `events`, the JSON-encoded to-device events to process.
+    #[napi(strict)]
+    pub async fn process_to_device_batch(
+        &self,
+        events: Vec<String>,
+    ) -> napi::Result<responses::ProcessedToDeviceResult> {
+        let mut errors = Vec::new();
+        let mut to_device_events = Vec::with_capacity(events.len());
+
+        for event in events {
+            match RawValue::from_string(event.clone()) {
+                Ok(event) => to_device_events.push(Raw::from_json(event)),
+                Err(error) => errors
+                    .push(responses::DecryptionError { description: error.to_string(), event }),
+            }
+        }
+
+        let encrypted_events = encrypted_event_count(&to_device_events);
+        let (processed_events, _) = self
+            .inner
+            .receive_sync_changes(EncryptionSyncChanges {
+                to_device_events,
+                changed_devices: &Default::default(),
+                one_time_keys_counts: &Default::default(),
+                unused_fallback_keys: None,
+                next_batch_token: None,
+            })
+            .await
+            .map_err(into_err)?;
+
+        self.olm_messages_received.fetch_add(
+            encrypted_events - encrypted_event_count(&processed_events),
+            Ordering::Relaxed,
+        );
+
+        let mut decrypted_events = Vec::with_capacity(processed_events.len());
+
+        for event in processed_events {
+            // Encrypted events that the SDK failed to decrypt are given back as is.
+            if event.get_field::<String>("type").ok().flatten().as_deref()
+                == Some("m.room.encrypted")
+            {
+                errors.push(responses::DecryptionError {
+                    description: "The to-device event could not be decrypted".to_owned(),
+                    event: event.json().get().to_owned(),
+                });
+            } else {
+                decrypted_events.push(event.json().get().to_owned());
+            }
+        }
+
+        Ok(responses::ProcessedToDeviceResult {
+            decrypted_events,
+            outgoing_requests: self.outgoing_requests().await?,
+            errors,
+        })
+    }
+
+    /// Handle an `m.room_key.withheld` to-device event.
+    ///
+    /// This is a narrower variant of `receive_sync_changes` for a
+    /// single withheld notification. The reason why the room key has
+    /// been withheld is stored, and can be retrieved with
+    /// `get_withheld_info`.
+    ///
+    /// # Arguments
+    ///
+    /// * `event`, the JSON-encoded `m.room_key.withheld` to-device event.
+    #[napi(strict)]
+    pub async fn handle_room_key_withheld(&self, event: String) -> napi::Result<()> {
+        let event =
+            Raw::<AnyToDeviceEvent>::from_json(RawValue::from_string(event).map_err(into_err)?);
+
+        let event_type = event.get_field::<String>("type").map_err(into_err)?;
+
+        if event_type.as_deref() != Some("m.room_key.withheld") {
+            return Err(napi::Error::from_reason(format!(
+                "Expected a `m.room_key.withheld` event, got `{}`",
+                event_type.unwrap_or_default()
+            )));
+        }
+
+        self.inner
+            .receive_sync_changes(EncryptionSyncChanges {
+                to_device_events: vec![event],
+                changed_devices: &Default::default(),
+                one_time_keys_counts: &Default::default(),
+                unused_fallback_keys: None,
+                next_batch_token: None,
+            })
+            .await
+            .map(|_| ())
+            .map_err(into_err)
+    }
+
+    /// Get the stored withheld notification of a Megolm session.
+    ///
+    /// Returns the JSON-encoded content of the `m.room_key.withheld`
+    /// event that has been received for the session, or `null` if the
+    /// room key hasn't been withheld. Only the `m.blacklisted` and
+    /// `m.unverified` codes are stored.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id`, the ID of the room the session belongs to.
+    /// * `session_id`, the ID of the Megolm session.
+    #[napi(strict)]
+    pub async fn get_withheld_info(
+        &self,
+        room_id: &identifiers::RoomId,
+        session_id: String,
+    ) -> napi::Result<Option<String>> {
+        let room_id = room_id.inner.clone();
+
+        (**self.inner.store())
+            .get_withheld_info(&room_id, &session_id)
+            .await
+            .map_err(into_err)?
+            .map(|event| serde_json::to_string(&event.content).map_err(into_err))
+            .transpose()
+    }
+
+    /// Handle the device list changes from a sync response.
+    ///
+    /// This is a typed alternative to the device changes part of
+    /// `receive_sync_changes`: it returns only the key query requests
+    /// that need to be sent out to refresh the device lists. The
+    /// responses of these requests need to be passed to the
+    /// `OlmMachine` with `mark_request_as_sent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `changed`, the users who have updated their device identity keys or
+    ///   who now share an encrypted room with the client.
+    /// * `left`, the users who no longer share encrypted rooms with the
+    ///   client.
+    #[napi(strict)]
+    pub async fn handle_sync_device_changes(
+        &self,
+        changed: Vec<&identifiers::UserId>,
+        left: Vec<&identifiers::UserId>,
+    ) -> napi::Result<Vec<requests::KeysQueryRequest>> {
+        let changed_devices = sync_events::DeviceLists::new(Some(changed), Some(left)).inner;
+
+        self.inner
+            .receive_sync_changes(EncryptionSyncChanges {
+                to_device_events: Vec::new(),
+                changed_devices: &changed_devices,
+                one_time_keys_counts: &Default::default(),
+                unused_fallback_keys: None,
+                next_batch_token: None,
+            })
+            .await
+            .map_err(into_err)?;
+
+        self.inner
+            .outgoing_requests()
+            .await
+            .map_err(into_err)?
+            .iter()
+            .filter_map(|request| match request.request() {
+                AnyOutgoingRequest::KeysQuery(keys_query_request) => {
+                    Some(requests::KeysQueryRequest::try_from((
+                        request.request_id().to_string(),
+                        keys_query_request,
+                    )))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Handle the one-time key counts of a sync response.
+    ///
+    /// This is a narrower variant of `receive_sync_changes` that only
+    /// feeds the `device_one_time_keys_count` field of `/sync` into the
+    /// `OlmMachine`. If more one-time keys need to be uploaded, a
+    /// `KeysUploadRequest` will be returned by `outgoing_requests`.
+    ///
+    /// # Arguments
+    ///
+    /// * `one_time_key_counts`, the current one-time keys counts that the sync
+    ///   response returned.
+    #[napi(strict)]
+    pub async fn process_sync_one_time_key_counts(
+        &self,
+        one_time_key_counts: HashMap<String, u32>,
+    ) -> napi::Result<()> {
+        let one_time_key_counts = one_time_key_counts
+            .iter()
+            .map(|(key, value)| (OneTimeKeyAlgorithm::from(key.as_str()), UInt::from(*value)))
+            .collect::<BTreeMap<_, _>>();
+
+        self.inner
+            .receive_sync_changes(EncryptionSyncChanges {
+                to_device_events: Vec::new(),
+                changed_devices: &Default::default(),
+                one_time_keys_counts: &one_time_key_counts,
+                unused_fallback_keys: None,
+                next_batch_token: None,
+            })
+            .await
+            .map(|_| ())
+            .map_err(into_err)
+    }
+
+    /// Handle the unused fallback key types of a sync response.
+    ///
+    /// This is a narrower variant of `receive_sync_changes` that only
+    /// feeds the `device_unused_fallback_key_types` field of `/sync`
+    /// into the `OlmMachine`. If a new fallback key needs to be
+    /// uploaded, a `KeysUploadRequest` will be returned by
+    /// `outgoing_requests`.
+    ///
+    /// # Arguments
+    ///
+    /// * `unused_fallback_keys`, the key algorithms of the fallback keys that
+    ///   the server still holds and haven't been used yet.
+    #[napi(strict)]
+    pub async fn process_unused_fallback_key_types(
+        &self,
+        unused_fallback_keys: Vec<String>,
+    ) -> napi::Result<()> {
+        let unused_fallback_keys = unused_fallback_keys
+            .into_iter()
+            .map(|key| OneTimeKeyAlgorithm::from(key.as_str()))
+            .collect::<Vec<_>>();
+
+        self.inner
+            .receive_sync_changes(EncryptionSyncChanges {
+                to_device_events: Vec::new(),
+                changed_devices: &Default::default(),
+                one_time_keys_counts: &Default::default(),
+                unused_fallback_keys: Some(&unused_fallback_keys),
+                next_batch_token: None,
+            })
+            .await
+            .map(|_| ())
+            .map_err(into_err)
+    }
+
+    /// Check whether a new fallback key needs to be uploaded.
+    ///
+    /// A new fallback key is generated once the server reports that the
+    /// previous one has been used, see `receive_sync_changes` or
+    /// `process_unused_fallback_key_types`. This returns the
+    /// `KeysUploadRequest` carrying the new fallback key, or `null` if
+    /// there is nothing to upload. The response needs to be passed back
+    /// to the state machine using `mark_request_as_sent`.
+    #[napi]
+    pub async fn generate_fallback_key_if_needed(
+        &self,
+    ) -> napi::Result<Option<requests::KeysUploadRequest>> {
+        for request in self.inner.outgoing_requests().await.map_err(into_err)? {
+            if let AnyOutgoingRequest::KeysUpload(keys_upload) = request.request() {
+                if !keys_upload.fallback_keys.is_empty() {
+                    return Ok(Some(requests::KeysUploadRequest::try_from((
+                        request.request_id().to_string(),
+                        keys_upload,
+                    ))?));
+                }
+            }
+        }
+
+        Ok(None)
     }
 
     /// Get the outgoing requests that need to be sent out.
@@ -275,6 +746,27 @@ impl OlmMachine {
             .collect()
     }
 
+    /// Force the creation of a request to upload the device keys of
+    /// this `OlmMachine`.
+    ///
+    /// Unlike `outgoing_requests`, this returns a `KeysUploadRequest`
+    /// even if the device keys have already been uploaded, e.g. to
+    /// publish them again after a change of the device display name.
+    /// The response needs to be passed back to the state machine using
+    /// `mark_request_as_sent`.
+    #[napi]
+    pub async fn force_send_device_keys(&self) -> napi::Result<requests::KeysUploadRequest> {
+        let account =
+            self.inner.store().load_account().await.map_err(into_err)?.ok_or_else(|| {
+                napi::Error::from_reason("The Olm account is missing from the store".to_owned())
+            })?;
+
+        let mut request = upload_keys::v3::Request::new();
+        request.device_keys = Some(Raw::new(&account.device_keys()).map_err(into_err)?.cast());
+
+        requests::KeysUploadRequest::try_from((TransactionId::new().to_string(), &request))
+    }
+
     /// Mark the request with the given request ID as sent.
     ///
     /// # Arguments
@@ -298,7 +790,42 @@ impl OlmMachine {
         self.inner
             .mark_request_as_sent(&transaction_id, &incoming_response)
             .await
-            .map(|_| true)
+            .map_err(into_err)?;
+
+        match request_type {
+            requests::RequestType::ToDevice => {
+                self.to_device_messages_sent.fetch_add(1, Ordering::Relaxed);
+            }
+            requests::RequestType::RoomMessage => {
+                self.room_messages_sent.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    /// Handle the `/keys/query` response that is done at login for the
+    /// members of the joined rooms.
+    ///
+    /// Unlike `mark_request_as_sent`, this doesn't need a request that
+    /// was returned by `outgoing_requests`. The devices and the
+    /// cross-signing identities of the response are stored; to keep them
+    /// up to date, the users need to be tracked with
+    /// `update_tracked_users`.
+    ///
+    /// # Arguments
+    ///
+    /// * `response`, the JSON-encoded `/keys/query` response.
+    #[napi(strict)]
+    pub async fn process_initial_device_list(&self, response: String) -> napi::Result<()> {
+        let response = response_from_string(response.as_str()).map_err(into_err)?;
+        let incoming_response =
+            responses::OwnedResponse::try_from((requests::RequestType::KeysQuery, response))?;
+
+        self.inner
+            .mark_request_as_sent(&TransactionId::new(), &incoming_response)
+            .await
             .map_err(into_err)
     }
 
@@ -358,6 +885,30 @@ impl OlmMachine {
         }
     }
 
+    /// Ensure that Olm sessions exist with all the devices of the given
+    /// users.
+    ///
+    /// This is the pre-flight step before sending to-device messages
+    /// to these users. It returns the key claiming requests that need
+    /// to be sent out for the devices we are missing an Olm session
+    /// with; the returned array is empty if all the sessions are
+    /// already established. Note that key claiming requests target the
+    /// `/keys/claim` endpoint and are not to-device requests.
+    ///
+    /// The responses of the requests need to be passed to the
+    /// `OlmMachine` with `mark_request_as_sent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `users`, the list of users we want to have Olm sessions with.
+    #[napi(strict)]
+    pub async fn ensure_olm_sessions_for_users(
+        &self,
+        users: Vec<&identifiers::UserId>,
+    ) -> napi::Result<Vec<requests::KeysClaimRequest>> {
+        Ok(self.get_missing_sessions(Some(users)).await?.into_iter().collect())
+    }
+
     /// Update the tracked users.
     ///
     /// This will mark users that weren’t seen before for a key query
@@ -378,6 +929,42 @@ impl OlmMachine {
         Ok(())
     }
 
+    /// Update the tracked users, like `update_tracked_users`, and
+    /// return the `KeysQueryRequest`s that query the devices of the
+    /// given users.
+    ///
+    /// This saves a call to `outgoing_requests`. The responses need
+    /// to be passed back to the state machine using
+    /// `mark_request_as_sent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `users`, an array over user IDs that should be tracked.
+    #[napi(strict)]
+    pub async fn ensure_devices_are_tracked(
+        &self,
+        users: Vec<&identifiers::UserId>,
+    ) -> napi::Result<Vec<requests::KeysQueryRequest>> {
+        let users = users.into_iter().map(|user| user.inner.clone()).collect::<Vec<_>>();
+
+        self.inner.update_tracked_users(users.iter().map(AsRef::as_ref)).await.map_err(into_err)?;
+
+        let mut keys_queries = Vec::new();
+
+        for request in self.inner.outgoing_requests().await.map_err(into_err)? {
+            if let AnyOutgoingRequest::KeysQuery(keys_query) = request.request() {
+                if users.iter().any(|user| keys_query.device_keys.contains_key(user)) {
+                    keys_queries.push(requests::KeysQueryRequest::try_from((
+                        request.request_id().to_string(),
+                        keys_query,
+                    ))?);
+                }
+            }
+        }
+
+        Ok(keys_queries)
+    }
+
     /// Get to-device requests to share a room key with users in a room.
     ///
     /// # Arguments
@@ -406,249 +993,1956 @@ impl OlmMachine {
             .collect()
     }
 
-    /// Encrypt a JSON-encoded content for the given room.
+    /// Process a `m.room.member` state event of a room.
+    ///
+    /// Users that join or are invited to the room are added to the
+    /// set of tracked users, so that their devices are queried. When a
+    /// user leaves or is banned from the room, its current outbound
+    /// Megolm session is discarded, so that a new room key is shared
+    /// before the next message is encrypted.
     ///
     /// # Arguments
     ///
-    /// * `room_id`, the ID of the room for which the message should be
-    ///   encrypted.
-    /// * `event_type`, the plaintext type of the event.
-    /// * `content`, the JSON-encoded content of the message that should be
-    ///   encrypted.
+    /// * `event`, the `m.room.member` event, encoded as JSON.
+    /// * `room_id`, the ID of the room the event belongs to.
     #[napi(strict)]
-    pub async fn encrypt_room_event(
+    pub async fn process_room_member_event(
         &self,
+        event: String,
         room_id: &identifiers::RoomId,
-        event_type: String,
-        content: String,
-    ) -> napi::Result<String> {
+    ) -> napi::Result<()> {
         let room_id = room_id.inner.clone();
-        let content = serde_json::from_str(content.as_str()).map_err(into_err)?;
-        serde_json::to_string(
-            &self
-                .inner
-                .encrypt_room_event_raw(&room_id, event_type.as_ref(), &content)
-                .await
-                .map_err(into_err)?,
-        )
-        .map_err(into_err)
-    }
+        let event: SyncRoomMemberEvent = serde_json::from_str(event.as_str()).map_err(into_err)?;
 
-    /// Decrypt an event from a room timeline.
-    ///
-    /// # Arguments
+        match event.membership() {
+            MembershipState::Join | MembershipState::Invite => {
+                self.inner
+                    .update_tracked_users([event.state_key().as_ref()])
+                    .await
+                    .map_err(into_err)?;
+            }
+
+            MembershipState::Leave | MembershipState::Ban => {
+                self.inner.discard_room_key(&room_id).await.map_err(into_err)?;
+            }
+
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the outbound Megolm session of a room has been
+    /// shared with the given device.
     ///
-    /// * `event`, the event that should be decrypted.
-    /// * `room_id`, the ID of the room where the event was sent to.
+    /// This is useful to decide whether a room key can be forwarded to
+    /// a device that requests it. Returns `false` if the session is not
+    /// the current outbound session of the room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id`, the ID of the room the session is used in.
+    /// * `session_id`, the ID of the Megolm session.
+    /// * `user_id`, the owner of the device.
+    /// * `device_id`, the unique ID of the device.
     #[napi(strict)]
-    pub async fn decrypt_room_event(
+    pub async fn is_megolm_session_shared_with(
         &self,
-        event: String,
         room_id: &identifiers::RoomId,
-    ) -> napi::Result<responses::DecryptedRoomEvent> {
-        let event = Raw::from_json(RawValue::from_string(event).map_err(into_err)?);
+        session_id: String,
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+    ) -> napi::Result<bool> {
         let room_id = room_id.inner.clone();
+        let user_id = user_id.inner.clone();
+        let device_id = device_id.inner.clone();
 
-        let decryption_settings =
-            DecryptionSettings { sender_device_trust_requirement: TrustRequirement::Untrusted };
+        let Some(session) =
+            self.inner.store().get_outbound_group_session(&room_id).await.map_err(into_err)?
+        else {
+            return Ok(false);
+        };
 
-        let room_event = self
+        if session.session_id() != session_id {
+            return Ok(false);
+        }
+
+        Ok(session
+            .pickle()
+            .await
+            .shared_with_set
+            .get(&user_id)
+            .and_then(|devices| devices.get(&device_id))
+            .is_some_and(|share_info| matches!(share_info, ShareInfo::Shared(_))))
+    }
+
+    /// Encrypt a JSON-encoded content for the given room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id`, the ID of the room for which the message should be
+    ///   encrypted.
+    /// * `event_type`, the plaintext type of the event.
+    /// * `content`, the JSON-encoded content of the message that should be
+    ///   encrypted.
+    #[napi(strict)]
+    pub async fn encrypt_room_event(
+        &self,
+        room_id: &identifiers::RoomId,
+        event_type: String,
+        content: String,
+    ) -> napi::Result<String> {
+        let room_id = room_id.inner.clone();
+        let content = serde_json::from_str(content.as_str()).map_err(into_err)?;
+        let encrypted = self
             .inner
-            .decrypt_room_event(&event, &room_id, &decryption_settings)
+            .encrypt_room_event_raw(&room_id, event_type.as_ref(), &content)
             .await
             .map_err(into_err)?;
 
-        Ok(room_event.into())
-    }
+        self.room_messages_sent.fetch_add(1, Ordering::Relaxed);
 
-    /// Get the status of the private cross signing keys.
-    ///
-    /// This can be used to check which private cross signing keys we
-    /// have stored locally.
-    #[napi]
-    pub async fn cross_signing_status(&self) -> olm::CrossSigningStatus {
-        self.inner.cross_signing_status().await.into()
+        serde_json::to_string(&encrypted).map_err(into_err)
     }
 
-    /// Create a new cross signing identity and get the upload request
-    /// to push the new public keys to the server.
-    ///
-    /// Warning: This will delete any existing cross signing keys that
-    /// might exist on the server and thus will reset the trust
-    /// between all the devices.
+    /// Encrypt a batch of JSON-encoded contents for rooms.
     ///
-    /// Uploading these keys will require user interactive auth.
+    /// This is a variant of `encrypt_room_event` for many events at
+    /// once, e.g. a message and its attachments. The events are
+    /// encrypted in order, and the encrypted contents are returned in
+    /// the same order. It fails if any of the events cannot be
+    /// encrypted.
     ///
     /// # Arguments
     ///
-    /// * `reset`, whether the method should create a new identity or use the
-    ///   existing one during the request. If set to true, the request will
-    ///   attempt to upload a new identity. If set to false, the request will
-    ///   attempt to upload the existing identity. Since the uploading process
-    ///   requires user interactive authentication, which involves sending out
-    ///   the same request multiple times, setting this argument to false
-    ///   enables you to reuse the same request.
-    #[napi]
-    pub async fn bootstrap_cross_signing(&self, reset: bool) -> napi::Result<()> {
-        self.inner.bootstrap_cross_signing(reset).await.map_err(into_err)?;
-        Ok(())
-    }
-
-    /// Sign the given message using our device key and if available
-    /// cross-signing master key.
+    /// * `events`, the events that should be encrypted, along with the ID of
+    ///   the room they're sent to.
     #[napi(strict)]
-    pub async fn sign(&self, message: String) -> napi::Result<types::Signatures> {
-        Ok(self.inner.sign(&message).await.map_err(into_err)?.into())
+    pub async fn batch_encrypt(
+        &self,
+        events: Vec<events::RoomEventToEncrypt>,
+    ) -> napi::Result<Vec<String>> {
+        let mut encrypted = Vec::with_capacity(events.len());
+
+        for events::RoomEventToEncrypt { room_id, event_type, content } in events {
+            let room_id = RoomId::parse(room_id.as_str()).map_err(into_err)?;
+            let content = serde_json::from_str(content.as_str()).map_err(into_err)?;
+
+            encrypted.push(
+                serde_json::to_string(
+                    &self
+                        .inner
+                        .encrypt_room_event_raw(&room_id, event_type.as_ref(), &content)
+                        .await
+                        .map_err(into_err)?,
+                )
+                .map_err(into_err)?,
+            );
+
+            self.room_messages_sent.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(encrypted)
     }
 
-    /// Store the backup decryption key in the crypto store.
+    /// Encrypt a JSON-encoded to-device content for the given device.
     ///
-    /// This is useful if the client wants to support gossiping of the backup
-    /// key.
+    /// Returns the JSON-encoded content of an `m.room.encrypted`
+    /// to-device event, using the `m.olm.v1.curve25519-aes-sha2`
+    /// algorithm. An Olm session must have been established with the
+    /// device beforehand, see `get_missing_sessions`. Encrypted
+    /// contents should be sent in the same order they were encrypted.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the owner of the recipient device.
+    /// * `device_id`, the unique ID of the recipient device.
+    /// * `event_type`, the plaintext type of the event.
+    /// * `content`, the JSON-encoded content of the message that should be
+    ///   encrypted.
     #[napi(strict)]
-    pub async fn save_backup_decryption_key(
+    pub async fn encrypt_olm_message(
         &self,
-        decryption_key: &BackupDecryptionKey,
-        version: String,
-    ) -> napi::Result<()> {
-        self.inner
-            .backup_machine()
-            .save_decryption_key(Some(decryption_key.inner.clone()), Some(version))
-            .await
-            .map_err(into_err)?;
-        Ok(())
-    }
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+        event_type: String,
+        content: String,
+    ) -> napi::Result<String> {
+        let user_id = user_id.inner.clone();
+        let device_id = device_id.inner.clone();
+        let content = serde_json::from_str(content.as_str()).map_err(into_err)?;
 
-    /// Get the backup keys we have saved in our store.
-    #[napi]
-    pub async fn get_backup_keys(&self) -> napi::Result<BackupKeys> {
-        let inner = self.inner.backup_machine().get_backup_keys().await.map_err(into_err)?;
-        Ok(BackupKeys {
-            decryption_key_base64: inner.decryption_key.map(|k| k.to_base64()),
-            backup_version: inner.backup_version,
-        })
+        let device =
+            self.inner.get_device(&user_id, &device_id, None).await.map_err(into_err)?.ok_or_else(
+                || napi::Error::from_reason(format!("The device `{device_id}` is unknown")),
+            )?;
+
+        Ok(device
+            .encrypt_event_raw(event_type.as_ref(), &content)
+            .await
+            .map_err(into_err)?
+            .json()
+            .get()
+            .to_owned())
     }
 
-    /// Check if the given backup has been verified by us or by another of our
-    /// devices that we trust.
+    /// Decrypt an event from a room timeline.
     ///
-    /// The `backup_info` should be a stringified JSON object with the following
-    /// format:
+    /// # Arguments
     ///
-    /// ```json
-    /// {
-    ///     "algorithm": "m.megolm_backup.v1.curve25519-aes-sha2",
-    ///     "auth_data": {
-    ///         "public_key":"XjhWTCjW7l59pbfx9tlCBQolfnIQWARoKOzjTOPSlWM",
-    ///         "signatures": {}
-    ///     }
-    /// }
-    /// ```
+    /// * `event`, the event that should be decrypted.
+    /// * `room_id`, the ID of the room where the event was sent to.
     #[napi(strict)]
-    pub async fn verify_backup(&self, backup_info: String) -> napi::Result<SignatureVerification> {
-        let backup_info: RoomKeyBackupInfo =
-            serde_json::from_str(backup_info.as_str()).map_err(into_err)?;
+    pub async fn decrypt_room_event(
+        &self,
+        event: String,
+        room_id: &identifiers::RoomId,
+    ) -> napi::Result<responses::DecryptedRoomEvent> {
+        let room_id = room_id.inner.clone();
 
-        Ok(SignatureVerification {
-            inner: self
-                .inner
-                .backup_machine()
-                .verify_backup(backup_info, false)
-                .await
-                .map_err(into_err)?,
-        })
+        self.decrypt_event(event, &room_id).await
     }
 
-    /// Activate the given backup key to be used with the given backup version.
+    /// Try to decrypt an event from a room timeline.
     ///
-    /// **Warning**: The caller needs to make sure that the given `BackupKey` is
-    /// trusted, otherwise we might be encrypting room keys that a malicious
-    /// party could decrypt.
+    /// This is a variant of `decrypt_room_event` that doesn't throw if
+    /// the event cannot be decrypted, but returns a `DecryptionError`
+    /// instead.
     ///
-    /// The [`OlmMachine::verify_backup`] method can be used to do so.
+    /// # Arguments
+    ///
+    /// * `event`, the event that should be decrypted.
+    /// * `room_id`, the ID of the room where the event was sent to.
     #[napi(strict)]
-    pub async fn enable_backup_v1(
+    pub async fn try_decrypt(
         &self,
-        public_key_base_64: String,
-        version: String,
-    ) -> napi::Result<()> {
-        let backup_key = MegolmV1BackupKey::from_base64(&public_key_base_64).map_err(into_err)?;
-        backup_key.set_version(version);
+        event: String,
+        room_id: &identifiers::RoomId,
+    ) -> Either<responses::DecryptedRoomEvent, responses::DecryptionError> {
+        let room_id = room_id.inner.clone();
 
-        self.inner.backup_machine().enable_backup_v1(backup_key).await.map_err(into_err)?;
-        Ok(())
+        match self.decrypt_event(event.clone(), &room_id).await {
+            Ok(decrypted) => Either::A(decrypted),
+            Err(error) => {
+                Either::B(responses::DecryptionError { description: error.reason, event })
+            }
+        }
     }
 
-    /// Are we able to encrypt room keys.
+    /// Decrypt a batch of events from room timelines.
     ///
-    /// This returns true if we have an active `BackupKey` and backup version
-    /// registered with the state machine.
-    #[napi]
-    pub async fn is_backup_enabled(&self) -> bool {
-        self.inner.backup_machine().enabled().await
-    }
-
-    /// Disable and reset our backup state.
+    /// This is a variant of `try_decrypt` for many events at once, e.g.
+    /// when a room timeline is loaded. The results are in the same order
+    /// as the events: a `DecryptedRoomEvent` for each event that could
+    /// be decrypted, a `DecryptionError` for the others.
     ///
-    /// This will remove any pending backup request, remove the backup key and
-    /// reset the backup state of each room key we have.
-    #[napi]
-    pub async fn disable_backup(&self) -> napi::Result<()> {
-        self.inner.backup_machine().disable_backup().await.map_err(into_err)?;
-        Ok(())
-    }
+    /// # Arguments
+    ///
+    /// * `events`, the events that should be decrypted, along with the ID of
+    ///   the room they were sent to.
+    #[napi(strict)]
+    pub async fn batch_decrypt(
+        &self,
+        events: Vec<events::RoomEventToDecrypt>,
+    ) -> Vec<Either<responses::DecryptedRoomEvent, responses::DecryptionError>> {
+        let mut results = Vec::with_capacity(events.len());
 
-    /// Encrypt a batch of room keys and return a request that needs to be sent
-    /// out to backup the room keys.
-    #[napi]
-    pub async fn backup_room_keys(&self) -> napi::Result<Option<requests::KeysBackupRequest>> {
-        match self.inner.backup_machine().backup().await.map_err(into_err)? {
-            Some((transaction_id, keys_backup_request)) => Ok(Some(
-                requests::KeysBackupRequest::try_from((
-                    transaction_id.to_string(),
-                    &keys_backup_request,
-                ))
-                .map_err(into_err)?,
-            )),
+        for events::RoomEventToDecrypt { event, room_id } in events {
+            let decrypted = match RoomId::parse(room_id.as_str()) {
+                Ok(room_id) => self.decrypt_event(event.clone(), &room_id).await,
+                Err(error) => Err(into_err(error)),
+            };
 
-            None => Ok(None),
+            results.push(match decrypted {
+                Ok(decrypted) => Either::A(decrypted),
+                Err(error) => {
+                    Either::B(responses::DecryptionError { description: error.reason, event })
+                }
+            });
         }
+
+        results
     }
 
-    /// Export room keys in unencrypted format for a given session_id.
-    /// This currently exports a json blob.
-    #[napi]
-    pub async fn export_room_keys_for_session(
+    /// Decrypt a Megolm ciphertext without the event it belongs to.
+    ///
+    /// This is a low-level variant of `decrypt_room_event` for use
+    /// cases where the full event isn't available anymore, e.g. to
+    /// rebuild a timeline. Returns the JSON-encoded decrypted event,
+    /// which contains the plaintext `type`, `content` and `room_id`,
+    /// and the given `origin_server_ts`. The sender and the event ID
+    /// aren't known, so they aren't part of the result.
+    ///
+    /// Unlike `decrypt_room_event`, the ciphertext isn't checked
+    /// against replay attacks, and the sender of the message isn't
+    /// checked against the owner of the Megolm session. The caller is
+    /// responsible for trusting the ciphertext.
+    ///
+    /// # Arguments
+    ///
+    /// * `ciphertext`, the Base64-encoded Megolm ciphertext.
+    /// * `session_id`, the ID of the Megolm session used to encrypt the
+    ///   ciphertext.
+    /// * `room_id`, the ID of the room the ciphertext was sent to.
+    /// * `timestamp`, the `origin_server_ts` of the event, in
+    ///   milliseconds. It must be a non-negative integer.
+    #[napi(strict)]
+    pub async fn decrypt_megolm_message(
         &self,
-        room_id: String,
+        ciphertext: String,
         session_id: String,
+        room_id: &identifiers::RoomId,
+        timestamp: BigInt,
     ) -> napi::Result<String> {
-        serde_json::to_string(
-            &self
-                .inner
-                .store()
-                .export_room_keys(|session| {
-                    session.session_id() == session_id && session.room_id() == &room_id
-                })
-                .await
-                .map_err(into_err)?,
-        )
-        .map_err(into_err)
-    }
+        let (negative, timestamp, lossless) = timestamp.get_u64();
 
-    /// Get the number of backed up room keys and the total number of room keys.
-    #[napi]
-    pub async fn room_key_counts(&self) -> napi::Result<RoomKeyCounts> {
-        Ok(self.inner.backup_machine().room_key_counts().await.map_err(into_err)?.into())
+        if negative || !lossless {
+            return Err(napi::Error::from_reason("The timestamp must be a non-negative integer"));
+        }
+
+        let room_id = room_id.inner.clone();
+        let session = self
+            .inner
+            .store()
+            .get_inbound_group_session(&room_id, &session_id)
+            .await
+            .map_err(into_err)?
+            .ok_or_else(|| {
+                napi::Error::from_reason(format!("The Megolm session `{session_id}` is unknown"))
+            })?;
+
+        // The SDK only decrypts whole events, so wrap the ciphertext in one.
+        // The sender, the event ID and the device ID are placeholders, they
+        // don't take part in the decryption and are removed from the result.
+        let event: EncryptedEvent = serde_json::from_value(serde_json::json!({
+            "type": "m.room.encrypted",
+            "sender": self.inner.user_id(),
+            "event_id": "$megolm-message",
+            "origin_server_ts": UInt::try_from(timestamp).map_err(into_err)?,
+            "content": {
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "ciphertext": ciphertext,
+                "sender_key": session.sender_key().to_base64(),
+                "device_id": self.inner.device_id(),
+                "session_id": session_id,
+            },
+        }))
+        .map_err(into_err)?;
+
+        let (mut decrypted, _) = session.decrypt(&event).await.map_err(into_err)?;
+
+        self.megolm_messages_received.fetch_add(1, Ordering::Relaxed);
+
+        decrypted.remove("sender");
+        decrypted.remove("event_id");
+        decrypted.remove("unsigned");
+
+        serde_json::to_string(&decrypted).map_err(into_err)
     }
 
-    /// Shut down the `OlmMachine`.
+    /// Get the local trust state of a device.
     ///
-    /// The `OlmMachine` cannot be used after this method has been called,
-    /// otherwise it will panic.
+    /// This is a fast path that doesn't need the identity of the device
+    /// owner to be loaded. Returns `null` if the device is unknown.
     ///
-    /// All associated resources will be closed too, like the crypto storage
-    /// connections.
+    /// # Arguments
+    ///
+    /// * `user_id`, the owner of the device.
+    /// * `device_id`, the unique ID of the device.
+    #[napi(strict)]
+    pub async fn local_trust_for_device(
+        &self,
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+    ) -> napi::Result<Option<device::LocalTrust>> {
+        let user_id = user_id.inner.clone();
+        let device_id = device_id.inner.clone();
+
+        Ok(self
+            .inner
+            .store()
+            .get_device(&user_id, &device_id)
+            .await
+            .map_err(into_err)?
+            .map(|device| device.local_trust_state().into()))
+    }
+
+    /// Get the cross signing identity of the given user, if known.
+    ///
+    /// Returns an `OwnUserIdentity` for our own user, and a
+    /// `UserIdentity` for the other users.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the unique ID of the user.
+    #[napi(strict)]
+    pub async fn get_identity(
+        &self,
+        user_id: &identifiers::UserId,
+    ) -> napi::Result<Option<Either<identities::OwnUserIdentity, identities::UserIdentity>>> {
+        let user_id = user_id.inner.clone();
+
+        Ok(self.inner.get_identity(&user_id, None).await.map_err(into_err)?.map(|identity| {
+            match identity {
+                matrix_sdk_crypto::UserIdentity::Own(identity) => {
+                    Either::A(identities::OwnUserIdentity {
+                        inner: identity,
+                        machine: (*self.inner).clone(),
+                    })
+                }
+                matrix_sdk_crypto::UserIdentity::Other(identity) => Either::B(identity.into()),
+            }
+        }))
+    }
+
+    /// Resolve the conflict raised by a change of the identity of a
+    /// user, i.e. of its master key.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the unique ID of the user, who must not be our own
+    ///   user.
+    /// * `action`, the decision of the local user.
+    #[napi(strict)]
+    pub async fn resolve_identity_conflict(
+        &self,
+        user_id: &identifiers::UserId,
+        action: identities::IdentityConflictAction,
+    ) -> napi::Result<()> {
+        let user_id = user_id.inner.clone();
+
+        let identity = self
+            .inner
+            .get_identity(&user_id, None)
+            .await
+            .map_err(into_err)?
+            .and_then(matrix_sdk_crypto::UserIdentity::other)
+            .ok_or_else(|| {
+                napi::Error::from_reason(format!("No identity of another user for `{user_id}`"))
+            })?;
+
+        match action {
+            identities::IdentityConflictAction::PinNew => {
+                if identity.has_verification_violation() {
+                    // Withdrawing the verification also pins the new identity.
+                    identity.withdraw_verification().await.map_err(into_err)?;
+                } else {
+                    identity.pin_current_master_key().await.map_err(into_err)?;
+                }
+            }
+
+            identities::IdentityConflictAction::Reject => {
+                let user_devices =
+                    self.inner.get_user_devices(&user_id, None).await.map_err(into_err)?;
+
+                for device in user_devices.devices() {
+                    device
+                        .set_local_trust(matrix_sdk_crypto::LocalTrust::BlackListed)
+                        .await
+                        .map_err(into_err)?;
+                }
+            }
+
+            identities::IdentityConflictAction::Ignore => {}
+        }
+
+        Ok(())
+    }
+
+    /// Get a specific device of a user.
+    ///
+    /// Returns `null` if the device is unknown.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the unique ID of the user that the device belongs to.
+    /// * `device_id`, the unique ID of the device.
+    #[napi(strict)]
+    pub async fn get_device(
+        &self,
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+    ) -> napi::Result<Option<device::Device>> {
+        let user_id = user_id.inner.clone();
+        let device_id = device_id.inner.clone();
+
+        Ok(self
+            .inner
+            .get_device(&user_id, &device_id, None)
+            .await
+            .map_err(into_err)?
+            .map(Into::into))
+    }
+
+    /// Get all the known devices of the given user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the unique ID of the user.
+    #[napi(strict)]
+    pub async fn get_user_devices(
+        &self,
+        user_id: &identifiers::UserId,
+    ) -> napi::Result<device::UserDevices> {
+        let user_id = user_id.inner.clone();
+
+        Ok(self.inner.get_user_devices(&user_id, None).await.map_err(into_err)?.into())
+    }
+
+    /// Get the devices of the tracked users with the given Ed25519 key.
+    ///
+    /// Devices are supposed to have a unique key, but nothing prevents
+    /// a malicious device from copying the key of another one, hence the
+    /// array.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`, the unpadded Base64-encoded Ed25519 key.
+    #[napi(strict)]
+    pub async fn get_devices_by_ed25519_key(
+        &self,
+        key: String,
+    ) -> napi::Result<Vec<device::Device>> {
+        let key = Ed25519PublicKey::from_base64(key.as_str()).map_err(into_err)?;
+
+        Ok(self
+            .tracked_devices()
+            .await?
+            .into_iter()
+            .filter(|device| device.ed25519_key() == Some(key))
+            .map(Into::into)
+            .collect())
+    }
+
+    /// Get the devices of the tracked users with the given Curve25519
+    /// key.
+    ///
+    /// This is useful to find the device that sent an Olm message, when
+    /// only its sender key is known.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`, the unpadded Base64-encoded Curve25519 key.
+    #[napi(strict)]
+    pub async fn get_devices_by_curve25519_key(
+        &self,
+        key: String,
+    ) -> napi::Result<Vec<device::Device>> {
+        let key = Curve25519PublicKey::from_base64(key.as_str()).map_err(into_err)?;
+
+        Ok(self
+            .tracked_devices()
+            .await?
+            .into_iter()
+            .filter(|device| device.curve25519_key() == Some(key))
+            .map(Into::into)
+            .collect())
+    }
+
+    /// Get the Curve25519 identity key of a device.
+    ///
+    /// Returns the unpadded Base64-encoded key, or `null` if the
+    /// device, or its Curve25519 key, is unknown.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the owner of the device.
+    /// * `device_id`, the unique ID of the device.
+    #[napi(strict)]
+    pub async fn get_device_curve25519_key(
+        &self,
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+    ) -> napi::Result<Option<String>> {
+        let user_id = user_id.inner.clone();
+        let device_id = device_id.inner.clone();
+
+        Ok(self
+            .inner
+            .store()
+            .get_device(&user_id, &device_id)
+            .await
+            .map_err(into_err)?
+            .and_then(|device| device.curve25519_key())
+            .map(|key| key.to_base64()))
+    }
+
+    /// Get the Ed25519 signing key of a device.
+    ///
+    /// Returns the unpadded Base64-encoded key, or `null` if the
+    /// device, or its Ed25519 key, is unknown.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the owner of the device.
+    /// * `device_id`, the unique ID of the device.
+    #[napi(strict)]
+    pub async fn get_device_ed25519_key(
+        &self,
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+    ) -> napi::Result<Option<String>> {
+        let user_id = user_id.inner.clone();
+        let device_id = device_id.inner.clone();
+
+        Ok(self
+            .inner
+            .store()
+            .get_device(&user_id, &device_id)
+            .await
+            .map_err(into_err)?
+            .and_then(|device| device.ed25519_key())
+            .map(|key| key.to_base64()))
+    }
+
+    /// Get the status of the private cross signing keys.
+    ///
+    /// This can be used to check which private cross signing keys we
+    /// have stored locally.
+    #[napi]
+    pub async fn cross_signing_status(&self) -> olm::CrossSigningStatus {
+        self.inner.cross_signing_status().await.into()
+    }
+
+    /// Create a new cross signing identity and get the upload request
+    /// to push the new public keys to the server.
+    ///
+    /// Warning: This will delete any existing cross signing keys that
+    /// might exist on the server and thus will reset the trust
+    /// between all the devices.
+    ///
+    /// Uploading these keys will require user interactive auth.
+    ///
+    /// # Arguments
+    ///
+    /// * `reset`, whether the method should create a new identity or use the
+    ///   existing one during the request. If set to true, the request will
+    ///   attempt to upload a new identity. If set to false, the request will
+    ///   attempt to upload the existing identity. Since the uploading process
+    ///   requires user interactive authentication, which involves sending out
+    ///   the same request multiple times, setting this argument to false
+    ///   enables you to reuse the same request.
+    ///
+    /// Returns the requests that need to be sent out, in order, to
+    /// publish the cross-signing keys.
+    #[napi]
+    pub async fn bootstrap_cross_signing(
+        &self,
+        reset: bool,
+    ) -> napi::Result<requests::BootstrapCrossSigningResult> {
+        self.inner.bootstrap_cross_signing(reset).await.map_err(into_err)?.try_into()
+    }
+
+    /// Sign the given message using our device key and if available
+    /// cross-signing master key.
+    #[napi(strict)]
+    pub async fn sign(&self, message: String) -> napi::Result<types::Signatures> {
+        Ok(self.inner.sign(&message).await.map_err(into_err)?.into())
+    }
+
+    /// Check whether there is a pending verification request with the
+    /// given user.
+    ///
+    /// A request is pending as long as it hasn't been completed or
+    /// cancelled. This is cheaper than fetching the verification
+    /// requests themselves, e.g. to show a notification badge.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the ID of the user the verification is happening with.
+    #[napi(strict)]
+    pub async fn has_pending_verification_request(&self, user_id: &identifiers::UserId) -> bool {
+        self.inner
+            .get_verification_requests(&user_id.inner)
+            .iter()
+            .any(|request| !request.is_done() && !request.is_cancelled())
+    }
+
+    /// Cancel all the verification flows with the given user.
+    ///
+    /// This cancels the verification requests that haven't been
+    /// completed or cancelled yet, along with their ongoing
+    /// verifications. The returned requests notify the other side of
+    /// the cancellation, they need to be sent out and their responses
+    /// passed back to the state machine using `mark_request_as_sent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the ID of the user the verifications are happening with.
+    #[napi(strict)]
+    pub async fn cancel_all_verifications_for_user(
+        &self,
+        user_id: &identifiers::UserId,
+    ) -> napi::Result<Vec<Either<requests::ToDeviceRequest, requests::RoomMessageRequest>>> {
+        self.inner
+            .get_verification_requests(&user_id.inner)
+            .iter()
+            .filter(|request| !request.is_done() && !request.is_cancelled())
+            .filter_map(|request| request.cancel())
+            .map(requests::OutgoingVerificationRequest)
+            .map(TryFrom::try_from)
+            .collect()
+    }
+
+    /// Accept a verification request with the given verification
+    /// methods.
+    ///
+    /// This looks the verification request up and accepts it in one
+    /// call. The returned outgoing request, if any, notifies the other
+    /// side of the acceptance; it needs to be sent out and its response
+    /// passed back to the state machine using `mark_request_as_sent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `flow_id`, the unique ID of the verification flow.
+    /// * `user_id`, the ID of the user the verification is happening with.
+    /// * `methods`, the verification methods we want to use.
+    #[napi(strict)]
+    pub async fn accept_verification_request(
+        &self,
+        flow_id: String,
+        user_id: &identifiers::UserId,
+        methods: Vec<verification::VerificationMethod>,
+    ) -> napi::Result<verification::AcceptedVerificationRequest> {
+        let request =
+            self.inner.get_verification_request(&user_id.inner, &flow_id).ok_or_else(|| {
+                napi::Error::from_reason(format!(
+                    "The verification request with the flow ID `{flow_id}` is unknown"
+                ))
+            })?;
+
+        let outgoing_request = request
+            .accept_with_methods(methods.into_iter().map(Into::into).collect())
+            .map(requests::OutgoingVerificationRequest)
+            .map(TryFrom::try_from)
+            .transpose()?;
+
+        Ok(verification::AcceptedVerificationRequest { request: request.into(), outgoing_request })
+    }
+
+    /// Get the verification request with the given flow ID, e.g. to
+    /// poll its state.
+    ///
+    /// Returns `null` if the verification request is unknown.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the ID of the user the verification is happening with.
+    /// * `flow_id`, the unique ID of the verification flow.
+    #[napi(strict)]
+    pub fn get_verification_request(
+        &self,
+        user_id: &identifiers::UserId,
+        flow_id: String,
+    ) -> Option<verification::VerificationRequest> {
+        self.inner.get_verification_request(&user_id.inner, flow_id).map(Into::into)
+    }
+
+    /// Get the verification with the given flow ID, e.g. to poll its
+    /// state.
+    ///
+    /// Returns `null` if the verification is unknown, e.g. because the
+    /// verification request hasn't transitioned to a verification
+    /// method yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the ID of the user the verification is happening with.
+    /// * `flow_id`, the unique ID of the verification flow.
+    #[napi(strict)]
+    pub fn get_verification(
+        &self,
+        user_id: &identifiers::UserId,
+        flow_id: String,
+    ) -> Option<verification::Verification> {
+        self.inner.get_verification(&user_id.inner, &flow_id).map(Into::into)
+    }
+
+    /// Request an interactive verification with our own user, i.e.
+    /// with all our other devices.
+    ///
+    /// Verification requests with other users happen in a room, see
+    /// `request_verification_in_room`. Returns the created
+    /// `VerificationRequest` and the to-device request that needs to be
+    /// sent out.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the ID of our own user.
+    /// * `methods`, the verification methods that we want to support;
+    ///   if not set, all the methods supported by the SDK are advertised.
+    #[napi(strict)]
+    pub async fn request_verification(
+        &self,
+        user_id: &identifiers::UserId,
+        methods: Option<Vec<verification::VerificationMethod>>,
+    ) -> napi::Result<verification::RequestedVerification> {
+        let user_id = user_id.inner.clone();
+
+        let identity =
+            self.inner.get_identity(&user_id, None).await.map_err(into_err)?.ok_or_else(|| {
+                napi::Error::from_reason(format!("The identity of `{user_id}` is unknown"))
+            })?;
+
+        let matrix_sdk_crypto::UserIdentity::Own(identity) = identity else {
+            return Err(napi::Error::from_reason(format!(
+                "`{user_id}` isn't our own user, the verification must be requested in a room"
+            )));
+        };
+
+        let (request, outgoing_request) = match methods {
+            Some(methods) => identity
+                .request_verification_with_methods(methods.into_iter().map(Into::into).collect())
+                .await
+                .map_err(into_err)?,
+            None => identity.request_verification().await.map_err(into_err)?,
+        };
+
+        Ok(verification::RequestedVerification {
+            request: request.into(),
+            outgoing_request: requests::OutgoingVerificationRequest(outgoing_request).try_into()?,
+        })
+    }
+
+    /// Get the JSON-encoded content of the `m.room.message` event that
+    /// requests an interactive verification with another user.
+    ///
+    /// The event needs to be sent to a room shared with the user, and
+    /// its ID passed to `request_verification_in_room`.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the ID of the user to verify.
+    /// * `methods`, the verification methods that we want to support;
+    ///   if not set, all the methods supported by the SDK are advertised.
+    #[napi(strict)]
+    pub async fn verification_request_content(
+        &self,
+        user_id: &identifiers::UserId,
+        methods: Option<Vec<verification::VerificationMethod>>,
+    ) -> napi::Result<String> {
+        let identity = self.other_user_identity(user_id.inner.clone()).await?;
+
+        serde_json::to_string(&identity.verification_request_content(
+            methods.map(|methods| methods.into_iter().map(Into::into).collect()),
+        ))
+        .map_err(into_err)
+    }
+
+    /// Create the verification request matching an interactive
+    /// verification request event that has been sent to a room, see
+    /// `verification_request_content`.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id`, the ID of the room the request event has been sent
+    ///   to.
+    /// * `user_id`, the ID of the user to verify.
+    /// * `request_event_id`, the ID of the sent request event.
+    /// * `methods`, the verification methods used in the request event.
+    #[napi(strict)]
+    pub async fn request_verification_in_room(
+        &self,
+        room_id: &identifiers::RoomId,
+        user_id: &identifiers::UserId,
+        request_event_id: String,
+        methods: Option<Vec<verification::VerificationMethod>>,
+    ) -> napi::Result<verification::VerificationRequest> {
+        let room_id = room_id.inner.clone();
+        let request_event_id = OwnedEventId::try_from(request_event_id).map_err(into_err)?;
+        let identity = self.other_user_identity(user_id.inner.clone()).await?;
+
+        Ok(identity
+            .request_verification(
+                &room_id,
+                &request_event_id,
+                methods.map(|methods| methods.into_iter().map(Into::into).collect()),
+            )
+            .into())
+    }
+
+    /// Start a SAS verification with the given device.
+    ///
+    /// This is a variant of `Device.start_verification` that looks the
+    /// device up. The returned outgoing request needs to be sent out and
+    /// its response passed back to the state machine using
+    /// `mark_request_as_sent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the ID of the owner of the device.
+    /// * `device_id`, the ID of the device to verify.
+    #[napi(strict)]
+    pub async fn start_sas_with_device(
+        &self,
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+    ) -> napi::Result<verification::StartedSas> {
+        let user_id = user_id.inner.clone();
+        let device_id = device_id.inner.clone();
+
+        let device = self
+            .inner
+            .get_device(&user_id, &device_id, None)
+            .await
+            .map_err(into_err)?
+            .ok_or_else(|| {
+                napi::Error::from_reason(format!("The device `{user_id}` `{device_id}` is unknown"))
+            })?;
+
+        device::Device::from(device).start_verification().await
+    }
+
+    /// Accept a SAS verification that has been started by the other
+    /// side.
+    ///
+    /// Returns the request that notifies the other side of the
+    /// acceptance, or `null` if the verification cannot be accepted,
+    /// e.g. because we started it.
+    ///
+    /// # Arguments
+    ///
+    /// * `flow_id`, the unique ID of the verification flow.
+    /// * `user_id`, the ID of the user the verification is happening with.
+    #[napi(strict)]
+    pub async fn accept_sas_verification(
+        &self,
+        flow_id: String,
+        user_id: &identifiers::UserId,
+    ) -> napi::Result<Option<Either<requests::ToDeviceRequest, requests::RoomMessageRequest>>> {
+        self.sas(&flow_id, &user_id.inner)?
+            .accept()
+            .map(requests::OutgoingVerificationRequest)
+            .map(TryFrom::try_from)
+            .transpose()
+    }
+
+    /// Get the emojis of the short auth string of a SAS verification.
+    ///
+    /// Returns `null` if the emojis aren't available yet, i.e. until the
+    /// keys have been exchanged, or if the other side doesn't support
+    /// the emoji method.
+    ///
+    /// # Arguments
+    ///
+    /// * `flow_id`, the unique ID of the verification flow.
+    /// * `user_id`, the ID of the user the verification is happening with.
+    #[napi(strict)]
+    pub async fn get_sas_emojis(
+        &self,
+        flow_id: String,
+        user_id: &identifiers::UserId,
+    ) -> napi::Result<Option<Vec<verification::Emoji>>> {
+        Ok(self
+            .sas(&flow_id, &user_id.inner)?
+            .emoji()
+            .map(|emojis| emojis.into_iter().map(Into::into).collect()))
+    }
+
+    /// Confirm that the short auth strings of a SAS verification match
+    /// on both sides.
+    ///
+    /// # Arguments
+    ///
+    /// * `flow_id`, the unique ID of the verification flow.
+    /// * `user_id`, the ID of the user the verification is happening with.
+    #[napi(strict)]
+    pub async fn confirm_sas_match(
+        &self,
+        flow_id: String,
+        user_id: &identifiers::UserId,
+    ) -> napi::Result<verification::ConfirmedVerification> {
+        let (outgoing_requests, signature_upload_request) =
+            self.sas(&flow_id, &user_id.inner)?.confirm().await.map_err(into_err)?;
+
+        verification::ConfirmedVerification::new(outgoing_requests, signature_upload_request)
+    }
+
+    /// Store the backup decryption key in the crypto store.
+    ///
+    /// This is useful if the client wants to support gossiping of the backup
+    /// key.
+    #[napi(strict)]
+    pub async fn save_backup_decryption_key(
+        &self,
+        decryption_key: &BackupDecryptionKey,
+        version: String,
+    ) -> napi::Result<()> {
+        self.inner
+            .backup_machine()
+            .save_decryption_key(Some(decryption_key.inner.clone()), Some(version))
+            .await
+            .map_err(into_err)?;
+        Ok(())
+    }
+
+    /// Get the backup keys we have saved in our store.
+    #[napi]
+    pub async fn get_backup_keys(&self) -> napi::Result<BackupKeys> {
+        let inner = self.inner.backup_machine().get_backup_keys().await.map_err(into_err)?;
+        Ok(BackupKeys {
+            decryption_key_base64: inner.decryption_key.map(|k| k.to_base64()),
+            backup_version: inner.backup_version,
+        })
+    }
+
+    /// Get the backup version and the public part of the backup key we
+    /// have saved in our store.
+    ///
+    /// The public key can be compared with the one from the backup
+    /// metadata of the server, to check that the stored key matches the
+    /// server-side backup.
+    ///
+    /// Returns `null` if no backup decryption key, or no backup version,
+    /// has been saved with `save_backup_decryption_key`.
+    #[napi]
+    pub async fn current_backup_key_version(&self) -> napi::Result<Option<BackupKeyVersion>> {
+        let backup_keys = self.inner.backup_machine().get_backup_keys().await.map_err(into_err)?;
+
+        Ok(backup_keys.decryption_key.zip(backup_keys.backup_version).map(
+            |(decryption_key, version)| BackupKeyVersion {
+                version,
+                public_key: decryption_key.megolm_v1_public_key().to_base64(),
+            },
+        ))
+    }
+
+    /// Check that the public key of a server-side backup matches the
+    /// backup decryption key we have saved in our store.
+    ///
+    /// This prevents backing up to, or restoring from, the wrong
+    /// backup version. Returns `false` if no backup decryption key has
+    /// been saved with `save_backup_decryption_key`.
+    ///
+    /// The `server_auth_data` should be the stringified `auth_data`
+    /// JSON object of the backup, with the following format:
+    ///
+    /// ```json
+    /// {
+    ///     "public_key":"XjhWTCjW7l59pbfx9tlCBQolfnIQWARoKOzjTOPSlWM",
+    ///     "signatures": {}
+    /// }
+    /// ```
+    #[napi(strict)]
+    pub async fn verify_key_backup_public_key(
+        &self,
+        server_auth_data: String,
+    ) -> napi::Result<bool> {
+        let auth_data: MegolmV1AuthData =
+            serde_json::from_str(server_auth_data.as_str()).map_err(into_err)?;
+        let backup_info = RoomKeyBackupInfo::MegolmBackupV1Curve25519AesSha2(auth_data);
+
+        Ok(self
+            .inner
+            .backup_machine()
+            .get_backup_keys()
+            .await
+            .map_err(into_err)?
+            .decryption_key
+            .is_some_and(|decryption_key| decryption_key.backup_key_matches(&backup_info)))
+    }
+
+    /// Sign the `auth_data` of a backup with our device key and, if
+    /// available, our cross-signing master key.
+    ///
+    /// This is needed when creating a new backup version, so that
+    /// our other devices can check that the backup is trusted. The
+    /// `auth_data` is returned as a stringified JSON object, with the
+    /// new signatures added.
+    ///
+    /// The `auth_data` should be a stringified JSON object with the
+    /// following format:
+    ///
+    /// ```json
+    /// {
+    ///     "public_key":"XjhWTCjW7l59pbfx9tlCBQolfnIQWARoKOzjTOPSlWM"
+    /// }
+    /// ```
+    #[napi(strict)]
+    pub async fn generate_backup_signature(&self, auth_data: String) -> napi::Result<String> {
+        let auth_data: MegolmV1AuthData =
+            serde_json::from_str(auth_data.as_str()).map_err(into_err)?;
+        let mut backup_info = RoomKeyBackupInfo::MegolmBackupV1Curve25519AesSha2(auth_data);
+
+        self.inner.backup_machine().sign_backup(&mut backup_info).await.map_err(into_err)?;
+
+        match backup_info {
+            RoomKeyBackupInfo::MegolmBackupV1Curve25519AesSha2(auth_data) => {
+                serde_json::to_string(&auth_data).map_err(into_err)
+            }
+            RoomKeyBackupInfo::Other { .. } => {
+                Err(napi::Error::from_reason("The backup algorithm has changed while signing"))
+            }
+        }
+    }
+
+    /// Check if the given backup has been verified by us or by another of our
+    /// devices that we trust.
+    ///
+    /// The `backup_info` should be a stringified JSON object with the following
+    /// format:
+    ///
+    /// ```json
+    /// {
+    ///     "algorithm": "m.megolm_backup.v1.curve25519-aes-sha2",
+    ///     "auth_data": {
+    ///         "public_key":"XjhWTCjW7l59pbfx9tlCBQolfnIQWARoKOzjTOPSlWM",
+    ///         "signatures": {}
+    ///     }
+    /// }
+    /// ```
+    #[napi(strict)]
+    pub async fn verify_backup(&self, backup_info: String) -> napi::Result<SignatureVerification> {
+        let backup_info: RoomKeyBackupInfo =
+            serde_json::from_str(backup_info.as_str()).map_err(into_err)?;
+
+        Ok(SignatureVerification {
+            inner: self
+                .inner
+                .backup_machine()
+                .verify_backup(backup_info, false)
+                .await
+                .map_err(into_err)?,
+        })
+    }
+
+    /// Activate the given backup key to be used with the given backup version.
+    ///
+    /// **Warning**: The caller needs to make sure that the given `BackupKey` is
+    /// trusted, otherwise we might be encrypting room keys that a malicious
+    /// party could decrypt.
+    ///
+    /// The [`OlmMachine::verify_backup`] method can be used to do so.
+    #[napi(strict)]
+    pub async fn enable_backup_v1(
+        &self,
+        public_key_base_64: String,
+        version: String,
+    ) -> napi::Result<()> {
+        let backup_key = MegolmV1BackupKey::from_base64(&public_key_base_64).map_err(into_err)?;
+        backup_key.set_version(version);
+
+        self.inner.backup_machine().enable_backup_v1(backup_key).await.map_err(into_err)?;
+        Ok(())
+    }
+
+    /// Activate the backup described by the given backup information,
+    /// as returned by the `/room_keys/version` endpoint.
+    ///
+    /// This is a variant of `enable_backup_v1` that dispatches on the
+    /// backup algorithm; only `m.megolm_backup.v1.curve25519-aes-sha2`
+    /// is supported. The same warning applies: the backup must be
+    /// trusted, see `verify_backup`.
+    ///
+    /// # Arguments
+    ///
+    /// * `algorithm`, the algorithm of the backup.
+    /// * `backup_version`, the version of the backup.
+    /// * `auth_data`, the JSON-encoded `auth_data` of the backup.
+    #[napi(strict)]
+    pub async fn enable_backup(
+        &self,
+        algorithm: String,
+        backup_version: String,
+        auth_data: String,
+    ) -> napi::Result<()> {
+        let auth_data: serde_json::Value =
+            serde_json::from_str(auth_data.as_str()).map_err(into_err)?;
+        let backup_info: RoomKeyBackupInfo = serde_json::from_value(
+            serde_json::json!({ "algorithm": algorithm, "auth_data": auth_data }),
+        )
+        .map_err(into_err)?;
+
+        let RoomKeyBackupInfo::MegolmBackupV1Curve25519AesSha2(auth_data) = backup_info else {
+            return Err(napi::Error::from_reason(format!(
+                "Unsupported backup algorithm `{algorithm}`"
+            )));
+        };
+
+        self.enable_backup_v1(auth_data.public_key.to_base64(), backup_version).await
+    }
+
+    /// Switch the active backup to the given backup version.
+    ///
+    /// This is needed when the server-side backup version has been
+    /// rotated. The backup decryption key saved with
+    /// `save_backup_decryption_key` is used to encrypt the room keys
+    /// for the new version; an error is raised if there is none.
+    ///
+    /// If the version changes, the pending backup request is dropped
+    /// and the backup state of all the room keys is reset, so that
+    /// `backup_room_keys` uploads all of them to the new version.
+    #[napi(strict)]
+    pub async fn prepare_backup_for_version(&self, version: String) -> napi::Result<()> {
+        let backup_machine = self.inner.backup_machine();
+
+        let Some(decryption_key) =
+            backup_machine.get_backup_keys().await.map_err(into_err)?.decryption_key
+        else {
+            return Err(napi::Error::from_reason(
+                "No backup decryption key has been saved in the store".to_owned(),
+            ));
+        };
+
+        if backup_machine.backup_version().await.as_deref() != Some(version.as_str()) {
+            backup_machine.disable_backup().await.map_err(into_err)?;
+        }
+
+        let backup_key = decryption_key.megolm_v1_public_key();
+        backup_key.set_version(version.clone());
+
+        backup_machine
+            .save_decryption_key(Some(decryption_key), Some(version))
+            .await
+            .map_err(into_err)?;
+        backup_machine.enable_backup_v1(backup_key).await.map_err(into_err)?;
+
+        Ok(())
+    }
+
+    /// Are we able to encrypt room keys.
+    ///
+    /// This returns true if we have an active `BackupKey` and backup version
+    /// registered with the state machine.
+    #[napi]
+    pub async fn is_backup_enabled(&self) -> bool {
+        self.inner.backup_machine().enabled().await
+    }
+
+    /// Disable and reset our backup state.
+    ///
+    /// This will remove any pending backup request, remove the backup key and
+    /// reset the backup state of each room key we have.
+    #[napi]
+    pub async fn disable_backup(&self) -> napi::Result<()> {
+        self.inner.backup_machine().disable_backup().await.map_err(into_err)?;
+        Ok(())
+    }
+
+    /// Encrypt a batch of room keys and return a request that needs to be sent
+    /// out to backup the room keys.
+    #[napi]
+    pub async fn backup_room_keys(&self) -> napi::Result<Option<requests::KeysBackupRequest>> {
+        match self.inner.backup_machine().backup().await.map_err(into_err)? {
+            Some((transaction_id, keys_backup_request)) => Ok(Some(
+                requests::KeysBackupRequest::try_from((
+                    transaction_id.to_string(),
+                    &keys_backup_request,
+                ))
+                .map_err(into_err)?,
+            )),
+
+            None => Ok(None),
+        }
+    }
+
+    /// Mark a room key as backed up in the current backup version.
+    ///
+    /// This is only needed when the room key has been uploaded outside
+    /// of `backup_room_keys`, whose requests mark their room keys as
+    /// backed up once they are passed to `mark_request_as_sent`. An
+    /// error is raised if the backup isn't enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id`, the ID of the room the room key belongs to.
+    /// * `session_id`, the ID of the Megolm session of the room key.
+    #[napi(strict)]
+    pub async fn mark_backup_key_as_uploaded(
+        &self,
+        room_id: &identifiers::RoomId,
+        session_id: String,
+    ) -> napi::Result<()> {
+        let room_id = room_id.inner.clone();
+
+        let Some(backup_version) = self.inner.backup_machine().backup_version().await else {
+            return Err(napi::Error::from_reason("The backup isn't enabled".to_owned()));
+        };
+
+        (**self.inner.store())
+            .mark_inbound_group_sessions_as_backed_up(
+                &backup_version,
+                &[(&room_id, session_id.as_str())],
+            )
+            .await
+            .map_err(into_err)
+    }
+
+    /// Restore the room keys downloaded from a server-side backup.
+    ///
+    /// The room keys are decrypted with the given backup decryption
+    /// key, and marked as backed up in the given backup version. Room
+    /// keys that cannot be decrypted are skipped, and aren't part of
+    /// the total of the result.
+    ///
+    /// # Arguments
+    ///
+    /// * `backup_key`, the private key of the backup.
+    /// * `version`, the version of the backup the room keys come from.
+    /// * `rooms`, the JSON-encoded body of the `/room_keys/keys` response.
+    #[napi(strict)]
+    pub async fn restore_backup(
+        &self,
+        backup_key: &BackupDecryptionKey,
+        version: String,
+        rooms: String,
+    ) -> napi::Result<olm::RoomKeyImportResult> {
+        let mut body: serde_json::Value = serde_json::from_str(rooms.as_str()).map_err(into_err)?;
+        let rooms = body
+            .get_mut("rooms")
+            .ok_or_else(|| napi::Error::from_reason("The body has no `rooms` field"))?
+            .take();
+        let rooms: BTreeMap<OwnedRoomId, RoomKeyBackup> =
+            serde_json::from_value(rooms).map_err(into_err)?;
+
+        let mut room_keys = Vec::new();
+
+        for (room_id, room_key_backup) in rooms {
+            for (session_id, key_backup_data) in room_key_backup.sessions {
+                let Ok(key_backup_data) = key_backup_data.deserialize() else {
+                    continue;
+                };
+
+                let Ok(room_key) =
+                    backup_key.inner.decrypt_session_data(key_backup_data.session_data)
+                else {
+                    continue;
+                };
+
+                room_keys.push(ExportedRoomKey::from_backed_up_room_key(
+                    room_id.clone(),
+                    session_id,
+                    room_key,
+                ));
+            }
+        }
+
+        self.inner
+            .store()
+            .import_room_keys(room_keys, Some(&version), |_, _| {})
+            .await
+            .map_err(into_err)?
+            .try_into()
+    }
+
+    /// Export all the room keys, encrypted with the given passphrase.
+    ///
+    /// Returns the armored export, in the room key export format of
+    /// the Matrix specification, that can be imported again with
+    /// `import_room_keys`.
+    ///
+    /// # Arguments
+    ///
+    /// * `passphrase`, the passphrase the export should be encrypted with.
+    /// * `rounds`, the number of PBKDF2 rounds used to derive the key from
+    ///   the passphrase.
+    #[napi(strict)]
+    pub async fn export_room_keys(
+        &self,
+        mut passphrase: String,
+        rounds: u32,
+    ) -> napi::Result<String> {
+        let keys = self.inner.store().export_room_keys(|_| true).await.map_err(into_err);
+        let export = keys.and_then(|keys| {
+            matrix_sdk_crypto::encrypt_room_key_export(&keys, &passphrase, rounds).map_err(into_err)
+        });
+
+        passphrase.zeroize();
+
+        export
+    }
+
+    /// Import the room keys of an encrypted export.
+    ///
+    /// # Arguments
+    ///
+    /// * `export`, the armored export, as returned by `export_room_keys`.
+    /// * `passphrase`, the passphrase the export is encrypted with.
+    /// * `progress_listener`, an optional callback called with the number of
+    ///   processed keys and the total number of keys while importing.
+    #[napi]
+    pub async fn import_room_keys(
+        &self,
+        export: String,
+        mut passphrase: String,
+        progress_listener: Option<ThreadsafeFunction<(u32, u32), ErrorStrategy::Fatal>>,
+    ) -> napi::Result<olm::RoomKeyImportResult> {
+        let keys = matrix_sdk_crypto::decrypt_room_key_export(export.as_bytes(), &passphrase)
+            .map_err(into_err);
+
+        passphrase.zeroize();
+
+        self.inner
+            .store()
+            .import_exported_room_keys(keys?, |processed, total| {
+                if let Some(progress_listener) = &progress_listener {
+                    progress_listener.call(
+                        (
+                            processed.try_into().unwrap_or(u32::MAX),
+                            total.try_into().unwrap_or(u32::MAX),
+                        ),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                }
+            })
+            .await
+            .map_err(into_err)?
+            .try_into()
+    }
+
+    /// Export room keys in unencrypted format for a given session_id.
+    /// This currently exports a json blob.
+    #[napi]
+    pub async fn export_room_keys_for_session(
+        &self,
+        room_id: String,
+        session_id: String,
+    ) -> napi::Result<String> {
+        serde_json::to_string(
+            &self
+                .inner
+                .store()
+                .export_room_keys(|session| {
+                    session.session_id() == session_id && session.room_id() == &room_id
+                })
+                .await
+                .map_err(into_err)?,
+        )
+        .map_err(into_err)
+    }
+
+    /// Get the number of backed up room keys and the total number of room keys.
+    #[napi]
+    pub async fn room_key_counts(&self) -> napi::Result<RoomKeyCounts> {
+        Ok(self.inner.backup_machine().room_key_counts().await.map_err(into_err)?.into())
+    }
+
+    /// Get the number of room keys that have not been backed up yet.
+    #[napi]
+    pub async fn get_unbacked_up_session_count(&self) -> napi::Result<u32> {
+        let counts = self.inner.backup_machine().room_key_counts().await.map_err(into_err)?;
+
+        Ok(counts.total.saturating_sub(counts.backed_up).try_into().unwrap_or(u32::MAX))
+    }
+
+    /// Get the number of room keys that have been backed up.
+    #[napi]
+    pub async fn get_backed_up_session_count(&self) -> napi::Result<u32> {
+        let counts = self.inner.backup_machine().room_key_counts().await.map_err(into_err)?;
+
+        Ok(counts.backed_up.try_into().unwrap_or(u32::MAX))
+    }
+
+    /// Get the total number of room keys.
+    #[napi]
+    pub async fn get_total_session_count(&self) -> napi::Result<u32> {
+        let counts = self.inner.backup_machine().room_key_counts().await.map_err(into_err)?;
+
+        Ok(counts.total.try_into().unwrap_or(u32::MAX))
+    }
+
+    /// Export the Olm sessions we have with the devices of the tracked
+    /// users.
+    ///
+    /// The pickled sessions contain the private state of the Olm
+    /// ratchets, so the export is encrypted and authenticated with the
+    /// given passphrase. The export also holds the identity keys of
+    /// this device, since the sessions can only be used by this
+    /// account. It can be imported again with
+    /// `import_olm_sessions_from_backup`.
+    ///
+    /// # Arguments
+    ///
+    /// * `passphrase`, the passphrase the export should be encrypted with.
+    #[napi(strict)]
+    pub async fn export_olm_sessions_for_backup(
+        &self,
+        mut passphrase: String,
+    ) -> napi::Result<String> {
+        let identity_keys = self.inner.identity_keys();
+        let sessions = self.pickled_sessions().await;
+        let export = sessions.and_then(|sessions| {
+            let export = serde_json::json!({
+                "curve25519_key": identity_keys.curve25519.to_base64(),
+                "ed25519_key": identity_keys.ed25519.to_base64(),
+                "sessions": sessions,
+            });
+
+            encrypt_export(&export, &passphrase)
+        });
+
+        passphrase.zeroize();
+
+        export
+    }
+
+    /// Import Olm sessions exported with `export_olm_sessions_for_backup`.
+    ///
+    /// The export is rejected if it comes from another account, i.e.
+    /// if its identity keys aren't the ones of this device. A session
+    /// is only imported if it's shared with a known device of a
+    /// tracked user, and if it isn't known already; the other
+    /// sessions, and the malformed ones, are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `export`, the encrypted sessions.
+    /// * `passphrase`, the passphrase the export is encrypted with.
+    #[napi(strict)]
+    pub async fn import_olm_sessions_from_backup(
+        &self,
+        export: String,
+        mut passphrase: String,
+    ) -> napi::Result<olm::OlmSessionsImportResult> {
+        let export = decrypt_export(&export, &passphrase);
+
+        passphrase.zeroize();
+
+        let mut export = export?;
+        let identity_keys = self.inner.identity_keys();
+
+        // The ratchets are bound to the identity keys of the account
+        // that exported them, so they are useless to another account.
+        if export.get("curve25519_key").and_then(serde_json::Value::as_str)
+            != Some(identity_keys.curve25519.to_base64().as_str())
+            || export.get("ed25519_key").and_then(serde_json::Value::as_str)
+                != Some(identity_keys.ed25519.to_base64().as_str())
+        {
+            return Err(napi::Error::from_reason(
+                "The Olm sessions have been exported from another account",
+            ));
+        }
+
+        let pickles: Vec<serde_json::Value> = serde_json::from_value(
+            export.get_mut("sessions").map(serde_json::Value::take).unwrap_or_default(),
+        )
+        .map_err(into_err)?;
+        let total = pickles.len();
+
+        let account =
+            self.inner.store().load_account().await.map_err(into_err)?.ok_or_else(|| {
+                napi::Error::from_reason("The Olm account is missing from the store".to_owned())
+            })?;
+        let our_device_keys = account.device_keys();
+
+        let known_sender_keys = self
+            .tracked_devices()
+            .await?
+            .iter()
+            .filter_map(|device| device.curve25519_key())
+            .collect::<HashSet<_>>();
+
+        let mut sessions = Vec::new();
+
+        for pickle in pickles {
+            // Malformed pickles are skipped, like the sessions we can't use.
+            let Ok(pickle) = serde_json::from_value::<PickledSession>(pickle) else { continue };
+
+            if !known_sender_keys.contains(&pickle.sender_key) {
+                continue;
+            }
+
+            let Ok(session) = Session::from_pickle(our_device_keys.clone(), pickle) else {
+                continue;
+            };
+            let existing_sessions = (**self.inner.store())
+                .get_sessions(&session.sender_key().to_base64())
+                .await
+                .map_err(into_err)?
+                .unwrap_or_default();
+
+            if existing_sessions
+                .iter()
+                .chain(&sessions)
+                .all(|s| s.session_id() != session.session_id())
+            {
+                sessions.push(session);
+            }
+        }
+
+        let imported = sessions.len();
+
+        (**self.inner.store())
+            .save_changes(Changes { sessions, ..Default::default() })
+            .await
+            .map_err(into_err)?;
+
+        if imported > 0 {
+            // The sessions have been saved behind the back of the session
+            // cache of the `Store`. The cache is cleared whenever our own
+            // device is saved, since the sessions hold a copy of its keys,
+            // so save it again with an unchanged local trust.
+            let own_device = self
+                .inner
+                .get_device(self.inner.user_id(), self.inner.device_id(), None)
+                .await
+                .map_err(into_err)?
+                .ok_or_else(|| {
+                    napi::Error::from_reason("Our own device is missing from the store")
+                })?;
+
+            own_device.set_local_trust(own_device.local_trust_state()).await.map_err(into_err)?;
+        }
+
+        Ok(olm::OlmSessionsImportResult {
+            imported: imported.try_into().map_err(into_err)?,
+            skipped: (total - imported).try_into().map_err(into_err)?,
+        })
+    }
+
+    /// Export the verification state of the tracked users, so that it
+    /// can be transferred to a new installation with
+    /// `import_verification_state`.
+    ///
+    /// The export contains the devices with a local trust state
+    /// (verified, blacklisted or ignored) and the master keys of the
+    /// identities that have been accepted, i.e. pinned or verified. It
+    /// is encrypted and authenticated with the given passphrase, so
+    /// that it can't be tampered with.
+    ///
+    /// # Arguments
+    ///
+    /// * `passphrase`, the passphrase the export should be encrypted with.
+    #[napi(strict)]
+    pub async fn export_verification_state(&self, mut passphrase: String) -> napi::Result<String> {
+        let state = self.verification_state().await;
+        let export = state.and_then(|state| encrypt_export(&state, &passphrase));
+
+        passphrase.zeroize();
+
+        export
+    }
+
+    /// Import a verification state exported with
+    /// `export_verification_state`.
+    ///
+    /// The export is decrypted and authenticated first, so that trust
+    /// records which don't come from one of our exports are rejected.
+    /// Each entry is then checked against the currently known devices
+    /// and identities: the local trust state of a device is only
+    /// imported if the device still has the same Ed25519 key, and an
+    /// identity is only pinned if it still has the same master key.
+    /// Other entries are ignored. Nothing is imported if an entry is
+    /// invalid.
+    ///
+    /// # Arguments
+    ///
+    /// * `export`, the encrypted verification state.
+    /// * `passphrase`, the passphrase the export is encrypted with.
+    #[napi(strict)]
+    pub async fn import_verification_state(
+        &self,
+        export: String,
+        mut passphrase: String,
+    ) -> napi::Result<()> {
+        let state = decrypt_export(&export, &passphrase);
+
+        passphrase.zeroize();
+
+        let state = state?;
+
+        let invalid_state = || napi::Error::from_reason("Invalid verification state".to_owned());
+        let entries =
+            |name| state.get(name).and_then(serde_json::Value::as_array).ok_or_else(invalid_state);
+        let field = |entry: &serde_json::Value, name| {
+            entry
+                .get(name)
+                .and_then(serde_json::Value::as_str)
+                .map(ToOwned::to_owned)
+                .ok_or_else(invalid_state)
+        };
+
+        // Validate all the entries before applying any of them, so that an
+        // invalid export doesn't leave a partially imported state behind.
+        let mut trusted_devices = Vec::new();
+
+        for entry in entries("devices")? {
+            let user_id = OwnedUserId::try_from(field(entry, "user_id")?).map_err(into_err)?;
+            let device_id = OwnedDeviceId::from(field(entry, "device_id")?);
+            let ed25519_key =
+                Ed25519PublicKey::from_base64(&field(entry, "ed25519_key")?).map_err(into_err)?;
+            let local_trust: matrix_sdk_crypto::LocalTrust = serde_json::from_value(
+                entry.get("local_trust").cloned().ok_or_else(invalid_state)?,
+            )
+            .map_err(into_err)?;
+
+            let Some(device) =
+                self.inner.get_device(&user_id, &device_id, None).await.map_err(into_err)?
+            else {
+                continue;
+            };
+
+            if device.ed25519_key() == Some(ed25519_key) {
+                trusted_devices.push((device, local_trust));
+            }
+        }
+
+        let mut pinned_identities = Vec::new();
+
+        for entry in entries("identities")? {
+            let user_id = OwnedUserId::try_from(field(entry, "user_id")?).map_err(into_err)?;
+            let master_key =
+                Ed25519PublicKey::from_base64(&field(entry, "master_key")?).map_err(into_err)?;
+
+            let Some(identity) = self
+                .inner
+                .get_identity(&user_id, None)
+                .await
+                .map_err(into_err)?
+                .and_then(matrix_sdk_crypto::UserIdentity::other)
+            else {
+                continue;
+            };
+
+            if identity.master_key().get_first_key() == Some(master_key) {
+                pinned_identities.push(identity);
+            }
+        }
+
+        for (device, local_trust) in trusted_devices {
+            device.set_local_trust(local_trust).await.map_err(into_err)?;
+        }
+
+        for identity in pinned_identities {
+            identity.pin_current_master_key().await.map_err(into_err)?;
+        }
+
+        Ok(())
+    }
+
+    /// Take an exclusive lock on the store of this `OlmMachine`.
+    ///
+    /// The lock is held in the store itself, so it is shared with the
+    /// other `OlmMachine`s using the same store, even from other Node.js
+    /// workers or processes. The lock is advisory: it only excludes the
+    /// callers that take it too, and doesn't prevent the other
+    /// `OlmMachine`s from accessing the store.
+    ///
+    /// If the lock is already taken, this method retries with an
+    /// exponential backoff starting at 10ms, and gives up with an error
+    /// after about 1.3 seconds.
+    #[napi]
+    pub async fn lock_for_current_thread(&self) -> napi::Result<OlmMachineLock> {
+        let lock = self
+            .inner
+            .store()
+            .create_store_lock(STORE_LOCK_KEY.to_owned(), TransactionId::new().to_string());
+        let guard = lock.spin_lock(None).await.map_err(into_err)?;
+
+        Ok(OlmMachineLock { guard: Some(guard) })
+    }
+
+    /// Store custom JSON-encoded data in the crypto store.
+    ///
+    /// This is meant for client extensions that need to persist state
+    /// alongside the encryption keys. The keys are namespaced, so they
+    /// can't clash with the values `matrix-sdk-crypto` stores itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`, the key under which the data is stored.
+    /// * `value`, the JSON-encoded data.
+    #[napi(strict)]
+    pub async fn set_custom_account_data(&self, key: String, value: String) -> napi::Result<()> {
+        serde_json::from_str::<&RawValue>(value.as_str()).map_err(into_err)?;
+
+        self.inner
+            .store()
+            .set_custom_value(&custom_account_data_key(&key), value.into_bytes())
+            .await
+            .map_err(into_err)
+    }
+
+    /// Get custom data stored with `set_custom_account_data`.
+    ///
+    /// Returns `null` if no data is stored under this key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`, the key under which the data is stored.
+    #[napi(strict)]
+    pub async fn get_custom_account_data(&self, key: String) -> napi::Result<Option<String>> {
+        self.inner
+            .store()
+            .get_custom_value(&custom_account_data_key(&key))
+            .await
+            .map_err(into_err)?
+            .map(|value| String::from_utf8(value).map_err(into_err))
+            .transpose()
+    }
+
+    /// Hold a secret temporarily in memory.
+    ///
+    /// This is meant for the cross-signing bootstrap flow, where secrets
+    /// need to be kept between the moment they are generated and the
+    /// moment they are uploaded to the secret storage. The secrets are
+    /// never persisted, and are zeroized once they are removed from
+    /// memory. An existing secret with the same name is replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`, the name of the secret.
+    /// * `value`, the value of the secret.
+    #[napi(strict)]
+    pub async fn add_temporary_secret(&self, name: String, value: String) {
+        self.temporary_secrets.lock().unwrap().insert(name, Zeroizing::new(value));
+    }
+
+    /// Get a secret previously added with `add_temporary_secret`.
+    ///
+    /// Returns `null` if there is no secret with this name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`, the name of the secret.
+    #[napi(strict)]
+    pub fn get_temporary_secret(&self, name: String) -> Option<String> {
+        self.temporary_secrets.lock().unwrap().get(&name).map(|value| value.as_str().to_owned())
+    }
+
+    /// Remove all the secrets added with `add_temporary_secret`.
+    #[napi]
+    pub fn clear_temporary_secrets(&self) {
+        self.temporary_secrets.lock().unwrap().clear();
+    }
+
+    /// Get the users whose cross-signing identity has changed since
+    /// this `OlmMachine` has been initialized, e.g. because their
+    /// master key has been replaced or their identity has been pinned.
+    ///
+    /// Identities received or created for the first time aren't
+    /// reported. Unlike `on_identity_change`, any change is reported,
+    /// not only changes of the master key. This is useful to batch
+    /// the notifications of identity changes, e.g. once per sync, along
+    /// with `clear_identity_changed_users`.
+    #[napi]
+    pub async fn get_identity_changed_users(&self) -> Vec<identifiers::UserId> {
+        self.identity_changed_users.lock().unwrap().iter().cloned().map(Into::into).collect()
+    }
+
+    /// Forget the users returned by `get_identity_changed_users`,
+    /// e.g. once the client has notified the user about the changes.
+    #[napi]
+    pub async fn clear_identity_changed_users(&self) {
+        self.identity_changed_users.lock().unwrap().clear();
+    }
+
+    /// Get the users who have new devices since this `OlmMachine` has
+    /// been initialized, or since `clear_new_device_users` has been
+    /// called.
+    ///
+    /// The devices are the ones reported by `on_new_device`. This is
+    /// useful to batch the notifications of new devices, e.g. once per
+    /// sync.
+    #[napi]
+    pub async fn new_device_users(&self) -> Vec<identifiers::UserId> {
+        self.new_device_users.lock().unwrap().iter().cloned().map(Into::into).collect()
+    }
+
+    /// Forget the users returned by `new_device_users`, e.g. once the
+    /// client has notified the user about the new devices.
+    #[napi]
+    pub async fn clear_new_device_users(&self) {
+        self.new_device_users.lock().unwrap().clear();
+    }
+
+    /// Register a callback which is called whenever the master key of
+    /// a tracked user has changed.
+    ///
+    /// The callback gets the ID of the user, the previous keys and the
+    /// new keys of the identity. The previous keys are the last ones
+    /// seen by this `OlmMachine`, and are `null` if they aren't known.
+    /// Identities received for the first time, and changes that keep
+    /// the master key, e.g. pinning an identity, aren't reported.
+    ///
+    /// The callback doesn't keep the Node.js process alive.
+    #[napi(
+        ts_args_type = "callback: (userId: UserId, old: CrossSigningPublicKeys | null, new: CrossSigningPublicKeys) => void"
+    )]
+    pub fn on_identity_change(&self, env: Env, callback: JsFunction) -> napi::Result<()> {
+        let machine = (*self.inner).clone();
+        let updates = machine.store().identities_stream_raw();
+
+        // The stream only holds the new identities, so remember the keys
+        // of the identities known when it starts and of the ones received.
+        let changes = stream::once(async move {
+            let mut known_keys = BTreeMap::new();
+
+            for user_id in machine.tracked_users().await.unwrap_or_default() {
+                if let Ok(Some(identity)) = (**machine.store()).get_user_identity(&user_id).await {
+                    known_keys.insert(user_id, identities::CrossSigningPublicKeys::from(&identity));
+                }
+            }
+
+            updates.scan(known_keys, |known_keys, (changes, _)| {
+                for identity in &changes.new {
+                    known_keys.insert(identity.user_id().to_owned(), identity.into());
+                }
+
+                let master_key_changes = changes
+                    .changed
+                    .iter()
+                    .filter_map(|identity| {
+                        let user_id = identity.user_id().to_owned();
+                        let new = identities::CrossSigningPublicKeys::from(identity);
+                        let old = known_keys.insert(user_id.clone(), new.clone());
+
+                        match old {
+                            Some(old) if old.has_same_master_key(&new) => None,
+                            old => Some((user_id, old, new)),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                future::ready(Some(stream::iter(master_key_changes)))
+            })
+        })
+        .flatten();
+
+        listen(env, callback, changes.flatten(), |(user_id, old, new)| {
+            vec![Either3::A(identifiers::UserId::from(user_id)), Either3::B(old), Either3::C(new)]
+        })
+    }
+
+    /// Register a callback which is called with the ID of a user and
+    /// the ID of a device whenever a new device of a tracked user is
+    /// discovered.
+    ///
+    /// The callback doesn't keep the Node.js process alive.
+    #[napi(ts_args_type = "callback: (userId: UserId, deviceId: DeviceId) => void")]
+    pub fn on_new_device(&self, env: Env, callback: JsFunction) -> napi::Result<()> {
+        let devices = self
+            .inner
+            .store()
+            .identities_stream_raw()
+            .flat_map(|(_, devices)| stream::iter(devices.new));
+
+        listen(env, callback, devices, device_callback_arguments)
+    }
+
+    /// Register a callback which is called with the ID of a user and
+    /// the ID of a device whenever a device is removed from the device
+    /// list of a tracked user.
+    ///
+    /// The callback doesn't keep the Node.js process alive.
+    #[napi(ts_args_type = "callback: (userId: UserId, deviceId: DeviceId) => void")]
+    pub fn on_device_deleted(&self, env: Env, callback: JsFunction) -> napi::Result<()> {
+        let devices = self
+            .inner
+            .store()
+            .identities_stream_raw()
+            .flat_map(|(_, devices)| stream::iter(devices.deleted));
+
+        listen(env, callback, devices, device_callback_arguments)
+    }
+
+    /// Shut down the `OlmMachine`.
+    ///
+    /// The `OlmMachine` cannot be used after this method has been called,
+    /// otherwise it will panic.
+    ///
+    /// All associated resources will be closed too, like the crypto storage
+    /// connections.
     ///
     /// # Safety
     ///
@@ -657,5 +2951,394 @@ impl OlmMachine {
     #[napi(strict)]
     pub fn close(&mut self) {
         self.inner = OlmMachineInner::Closed;
+        self.clear_temporary_secrets();
+    }
+}
+
+#[cfg(feature = "qrcode")]
+#[napi]
+impl OlmMachine {
+    /// Generate a QR code for a verification request, to be shown to
+    /// and scanned by the other side.
+    ///
+    /// Returns `null` if the verification request isn't ready, or if
+    /// the other side doesn't support scanning QR codes.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the ID of the user the verification is happening with.
+    /// * `device_id`, the ID of the device the verification is happening
+    ///   with.
+    /// * `flow_id`, the unique ID of the verification flow.
+    #[napi(strict)]
+    pub async fn start_qr_verification(
+        &self,
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+        flow_id: String,
+    ) -> napi::Result<Option<verification::QrCode>> {
+        let request = self.verification_request(&flow_id, &user_id.inner, &device_id.inner)?;
+
+        Ok(request.generate_qr_code().await.map_err(into_err)?.map(Into::into))
+    }
+
+    /// Process a QR code scanned from the other side.
+    ///
+    /// The verification flow is the one whose flow ID is encoded in the
+    /// QR code. The returned outgoing request, if any, notifies the
+    /// other side of the scan; it needs to be sent out and its response
+    /// passed back to the state machine using `mark_request_as_sent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id`, the ID of the user the verification is happening with.
+    /// * `device_id`, the ID of the device the verification is happening
+    ///   with.
+    /// * `qr_code_data`, the bytes decoded from the scanned QR code.
+    #[napi(strict)]
+    pub async fn scan_qr_code(
+        &self,
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+        qr_code_data: Uint8Array,
+    ) -> napi::Result<verification::ScanResult> {
+        let data = QrVerificationData::from_bytes(qr_code_data.as_ref()).map_err(into_err)?;
+        let request =
+            self.verification_request(data.flow_id(), &user_id.inner, &device_id.inner)?;
+
+        let qr = request.scan_qr_code(data).await.map_err(into_err)?.ok_or_else(|| {
+            napi::Error::from_reason(format!(
+                "The verification request with the flow ID `{}` isn't ready to scan a QR code",
+                request.flow_id().as_str()
+            ))
+        })?;
+
+        let outgoing_request = qr
+            .reciprocate()
+            .map(requests::OutgoingVerificationRequest)
+            .map(TryFrom::try_from)
+            .transpose()?;
+
+        Ok(verification::ScanResult { qr: qr.into(), outgoing_request })
+    }
+
+    /// Confirm that the other side has scanned our QR code.
+    ///
+    /// Use `confirm_sas_match` for SAS verifications instead. The
+    /// returned requests notify the other side of the confirmation;
+    /// they need to be sent out and their responses passed back to the
+    /// state machine using `mark_request_as_sent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `flow_id`, the unique ID of the verification flow.
+    /// * `user_id`, the ID of the user the verification is happening with.
+    #[napi(strict)]
+    pub async fn confirm_verification(
+        &self,
+        flow_id: String,
+        user_id: &identifiers::UserId,
+    ) -> napi::Result<Vec<Either<requests::ToDeviceRequest, requests::RoomMessageRequest>>> {
+        let qr = self
+            .inner
+            .get_verification(&user_id.inner, &flow_id)
+            .and_then(|verification| verification.qr_v1())
+            .ok_or_else(|| {
+                napi::Error::from_reason(format!(
+                    "The QR code verification with the flow ID `{flow_id}` is unknown"
+                ))
+            })?;
+
+        qr.confirm_scanning()
+            .map(requests::OutgoingVerificationRequest)
+            .map(TryFrom::try_from)
+            .into_iter()
+            .collect()
+    }
+}
+
+impl OlmMachine {
+    /// Get the pickles of the Olm sessions we have with the devices of
+    /// the tracked users, see `export_olm_sessions_for_backup`.
+    async fn pickled_sessions(&self) -> napi::Result<Vec<PickledSession>> {
+        let mut sessions = Vec::new();
+
+        for device in self.tracked_devices().await? {
+            let Some(sender_key) = device.curve25519_key() else { continue };
+
+            // Go through the underlying crypto store, the `Store` wrapper doesn't
+            // expose the sessions.
+            for session in (**self.inner.store())
+                .get_sessions(&sender_key.to_base64())
+                .await
+                .map_err(into_err)?
+                .unwrap_or_default()
+            {
+                sessions.push(session.pickle().await);
+            }
+        }
+
+        Ok(sessions)
+    }
+
+    /// Get the verification state of the tracked users, see
+    /// `export_verification_state`.
+    async fn verification_state(&self) -> napi::Result<serde_json::Value> {
+        let mut devices = Vec::new();
+
+        for device in self.tracked_devices().await? {
+            let local_trust = device.local_trust_state();
+
+            if local_trust == matrix_sdk_crypto::LocalTrust::Unset {
+                continue;
+            }
+
+            let Some(ed25519_key) = device.ed25519_key() else { continue };
+
+            devices.push(serde_json::json!({
+                "user_id": device.user_id(),
+                "device_id": device.device_id(),
+                "ed25519_key": ed25519_key.to_base64(),
+                "local_trust": local_trust,
+            }));
+        }
+
+        let mut identities = Vec::new();
+
+        for user_id in self.inner.tracked_users().await.map_err(into_err)? {
+            let Some(identity) = self
+                .inner
+                .get_identity(&user_id, None)
+                .await
+                .map_err(into_err)?
+                .and_then(matrix_sdk_crypto::UserIdentity::other)
+            else {
+                continue;
+            };
+
+            if identity.identity_needs_user_approval() {
+                continue;
+            }
+
+            let Some(master_key) = identity.master_key().get_first_key() else { continue };
+
+            identities.push(serde_json::json!({
+                "user_id": user_id,
+                "master_key": master_key.to_base64(),
+            }));
+        }
+
+        Ok(serde_json::json!({
+            "devices": devices,
+            "identities": identities,
+        }))
+    }
+
+    /// Get the identity of a user that isn't our own.
+    async fn other_user_identity(
+        &self,
+        user_id: OwnedUserId,
+    ) -> napi::Result<matrix_sdk_crypto::OtherUserIdentity> {
+        self.inner
+            .get_identity(&user_id, None)
+            .await
+            .map_err(into_err)?
+            .and_then(|identity| identity.other())
+            .ok_or_else(|| {
+                napi::Error::from_reason(format!(
+                    "The identity of `{user_id}` is unknown, or is our own identity"
+                ))
+            })
+    }
+
+    /// Get the verification request with the given flow ID, checking
+    /// that it's happening with the given device.
+    #[cfg(feature = "qrcode")]
+    fn verification_request(
+        &self,
+        flow_id: &str,
+        user_id: &matrix_sdk_common::ruma::UserId,
+        device_id: &matrix_sdk_common::ruma::DeviceId,
+    ) -> napi::Result<matrix_sdk_crypto::VerificationRequest> {
+        self.inner
+            .get_verification_request(user_id, flow_id)
+            .filter(|request| {
+                request
+                    .other_device_id()
+                    .map_or(true, |other_device_id| other_device_id == device_id)
+            })
+            .ok_or_else(|| {
+                napi::Error::from_reason(format!(
+                    "The verification request with the flow ID `{flow_id}` is unknown"
+                ))
+            })
+    }
+
+    /// Get the SAS verification with the given flow ID.
+    fn sas(
+        &self,
+        flow_id: &str,
+        user_id: &matrix_sdk_common::ruma::UserId,
+    ) -> napi::Result<matrix_sdk_crypto::Sas> {
+        self.inner
+            .get_verification(user_id, flow_id)
+            .and_then(|verification| verification.sas_v1())
+            .ok_or_else(|| {
+                napi::Error::from_reason(format!(
+                    "The SAS verification with the flow ID `{flow_id}` is unknown"
+                ))
+            })
+    }
+
+    /// Decrypt an event from a room timeline, see `decrypt_room_event`.
+    async fn decrypt_event(
+        &self,
+        event: String,
+        room_id: &RoomId,
+    ) -> napi::Result<responses::DecryptedRoomEvent> {
+        let event = Raw::from_json(RawValue::from_string(event).map_err(into_err)?);
+
+        let decryption_settings =
+            DecryptionSettings { sender_device_trust_requirement: TrustRequirement::Untrusted };
+
+        let room_event = self
+            .inner
+            .decrypt_room_event(&event, room_id, &decryption_settings)
+            .await
+            .map_err(into_err)?;
+
+        self.megolm_messages_received.fetch_add(1, Ordering::Relaxed);
+
+        Ok(room_event.into())
+    }
+
+    /// Get all the known devices of the tracked users.
+    async fn tracked_devices(&self) -> napi::Result<Vec<matrix_sdk_crypto::Device>> {
+        let mut devices = Vec::new();
+
+        for user_id in self.inner.tracked_users().await.map_err(into_err)? {
+            let user_devices =
+                self.inner.get_user_devices(&user_id, None).await.map_err(into_err)?;
+            devices.extend(user_devices.devices());
+        }
+
+        Ok(devices)
     }
 }
+
+/// The arguments of the device callbacks, i.e. of
+/// `OlmMachine.on_new_device` and `OlmMachine.on_device_deleted`.
+fn device_callback_arguments(
+    device: DeviceData,
+) -> Vec<Either<identifiers::UserId, identifiers::DeviceId>> {
+    vec![
+        Either::A(device.user_id().to_owned().into()),
+        Either::B(device.device_id().to_owned().into()),
+    ]
+}
+
+/// Call the JavaScript `callback` with each item of `stream`, from a
+/// task that runs until the stream ends. `to_arguments` converts an
+/// item into the arguments of the callback.
+fn listen<T, V>(
+    env: Env,
+    callback: JsFunction,
+    stream: impl Stream<Item = T> + Send + 'static,
+    to_arguments: impl Fn(T) -> Vec<V> + Send + 'static,
+) -> napi::Result<()>
+where
+    T: Send + 'static,
+    V: ToNapiValue,
+{
+    let mut callback: ThreadsafeFunction<T, ErrorStrategy::Fatal> =
+        callback.create_threadsafe_function(0, move |context| Ok(to_arguments(context.value)))?;
+
+    // Don't prevent the Node.js process from exiting.
+    callback.unref(&env)?;
+
+    spawn(async move {
+        let mut stream = pin!(stream);
+
+        while let Some(item) = stream.next().await {
+            callback.call(item, ThreadsafeFunctionCallMode::NonBlocking);
+        }
+    });
+
+    Ok(())
+}
+
+/// Collect the user IDs yielded by a stream into a set, in the
+/// background.
+///
+/// The collection stops once the returned set has been dropped.
+fn collect_user_ids(
+    stream: impl Stream<Item = OwnedUserId> + Send + 'static,
+) -> Arc<Mutex<BTreeSet<OwnedUserId>>> {
+    let user_ids = Arc::new(Mutex::new(BTreeSet::new()));
+    let weak_user_ids = Arc::downgrade(&user_ids);
+
+    spawn(async move {
+        let mut stream = pin!(stream);
+
+        while let Some(user_id) = stream.next().await {
+            let Some(user_ids) = weak_user_ids.upgrade() else {
+                break;
+            };
+
+            user_ids.lock().unwrap().insert(user_id);
+        }
+    });
+
+    user_ids
+}
+
+/// Count the encrypted events among the given to-device events.
+///
+/// The SDK gives back the to-device events it failed to decrypt as is,
+/// so comparing the counts before and after the processing tells how
+/// many events have been decrypted.
+fn encrypted_event_count(events: &[Raw<AnyToDeviceEvent>]) -> u32 {
+    events
+        .iter()
+        .filter(|event| {
+            event.get_field::<String>("type").ok().flatten().as_deref() == Some("m.room.encrypted")
+        })
+        .count()
+        .try_into()
+        .unwrap_or(u32::MAX)
+}
+
+/// Encrypt an export with a passphrase, see `decrypt_export`.
+///
+/// The payload is encrypted and authenticated with a random
+/// `StoreCipher`, itself exported encrypted with a key derived from the
+/// passphrase. The result is a JSON-encoded object holding both, in
+/// unpadded base64.
+fn encrypt_export(payload: &serde_json::Value, passphrase: &str) -> napi::Result<String> {
+    let cipher = StoreCipher::new().map_err(into_err)?;
+
+    serde_json::to_string(&serde_json::json!({
+        "cipher": base64_encode(cipher.export(passphrase).map_err(into_err)?),
+        "payload": base64_encode(cipher.encrypt_value(payload).map_err(into_err)?),
+    }))
+    .map_err(into_err)
+}
+
+/// Decrypt an export encrypted with `encrypt_export`.
+///
+/// Fails if the passphrase is wrong, or if the export has been
+/// tampered with.
+fn decrypt_export(export: &str, passphrase: &str) -> napi::Result<serde_json::Value> {
+    let export: serde_json::Value = serde_json::from_str(export).map_err(into_err)?;
+    let field = |name| {
+        export
+            .get(name)
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| napi::Error::from_reason("Invalid encrypted export".to_owned()))
+            .and_then(|value| base64_decode(value).map_err(into_err))
+    };
+
+    let cipher = StoreCipher::import(passphrase, &field("cipher")?).map_err(into_err)?;
+
+    cipher.decrypt_value(&field("payload")?).map_err(into_err)
+}
@@ -1,7 +1,11 @@
 //! Olm types.
 
+use std::collections::BTreeMap;
+
 use napi_derive::*;
 
+use crate::into_err;
+
 /// Struct representing the state of our private cross signing keys,
 /// it shows which private cross signing keys we have locally stored.
 #[napi]
@@ -38,3 +42,110 @@ impl CrossSigningStatus {
         self.inner.has_user_signing
     }
 }
+
+/// Diagnostic information about the Olm account of an `OlmMachine`.
+#[napi(object)]
+#[derive(Debug)]
+pub struct AccountInfo {
+    /// Has the Olm account been shared with the server, i.e. have the
+    /// device keys been uploaded.
+    pub olm_account_is_shared: bool,
+
+    /// The number of signed one-time keys we know to be uploaded on
+    /// the server.
+    pub one_time_key_count: u32,
+
+    /// Has a fallback key been generated at least once for this
+    /// account.
+    pub fallback_key_generated: bool,
+
+    /// The Ed25519 public identity key of the device, encoded as
+    /// unpadded base64.
+    pub device_key_fingerprint: String,
+}
+
+impl From<&matrix_sdk_crypto::olm::Account> for AccountInfo {
+    fn from(account: &matrix_sdk_crypto::olm::Account) -> Self {
+        Self {
+            olm_account_is_shared: account.shared(),
+            one_time_key_count: account.uploaded_key_count().try_into().unwrap_or(u32::MAX),
+            // The creation time of the fallback key is only exposed by the
+            // pickle. It's a temporary, so its private keys are zeroized
+            // as soon as the field has been read.
+            fallback_key_generated: account.pickle().fallback_key_creation_timestamp.is_some(),
+            device_key_fingerprint: account.identity_keys().ed25519.to_base64(),
+        }
+    }
+}
+
+/// The result of an import of Olm sessions.
+#[napi(object)]
+#[derive(Debug)]
+pub struct OlmSessionsImportResult {
+    /// The number of sessions that were imported.
+    pub imported: u32,
+
+    /// The number of sessions that were skipped, because they were
+    /// already known, or because they aren't shared with a known device.
+    pub skipped: u32,
+}
+
+/// The result of an import of room keys, see
+/// `OlmMachine.import_room_keys`.
+#[napi(object)]
+#[derive(Debug)]
+pub struct RoomKeyImportResult {
+    /// The total number of room keys that were found in the export.
+    pub total: u32,
+
+    /// The number of room keys that were imported.
+    pub imported: u32,
+
+    /// The JSON-encoded map of the imported keys, from the room ID to
+    /// the list of the imported session IDs.
+    pub keys: String,
+}
+
+impl TryFrom<matrix_sdk_crypto::RoomKeyImportResult> for RoomKeyImportResult {
+    type Error = napi::Error;
+
+    fn try_from(result: matrix_sdk_crypto::RoomKeyImportResult) -> Result<Self, Self::Error> {
+        let keys = result
+            .keys
+            .into_iter()
+            .map(|(room_id, sessions)| {
+                (room_id.to_string(), sessions.into_values().flatten().collect::<Vec<_>>())
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        Ok(Self {
+            total: result.total_count.try_into().unwrap_or(u32::MAX),
+            imported: result.imported_count.try_into().unwrap_or(u32::MAX),
+            keys: serde_json::to_string(&keys).map_err(into_err)?,
+        })
+    }
+}
+
+/// The number of messages an `OlmMachine` has decrypted since it has
+/// been initialized, see `OlmMachine.get_receive_count`.
+#[napi(object)]
+#[derive(Debug)]
+pub struct ReceiveCount {
+    /// The number of to-device events decrypted with Olm.
+    pub olm_messages: u32,
+
+    /// The number of room events decrypted with Megolm.
+    pub megolm_messages: u32,
+}
+
+/// The number of messages an `OlmMachine` has sent since it has been
+/// initialized, see `OlmMachine.get_send_count`.
+#[napi(object)]
+#[derive(Debug)]
+pub struct SendCount {
+    /// The number of to-device requests that have been sent out.
+    pub to_device_messages: u32,
+
+    /// The number of room events that have been encrypted or sent out.
+    pub room_messages: u32,
+}
@@ -10,12 +10,17 @@ use matrix_sdk_common::ruma::{
     },
     events::EventContent,
 };
-use matrix_sdk_crypto::types::requests::{
-    AnyOutgoingRequest, KeysBackupRequest as RumaKeysBackupRequest,
-    KeysQueryRequest as RumaKeysQueryRequest, OutgoingRequest as SdkOutgoingRequest,
-    RoomMessageRequest as RumaRoomMessageRequest, ToDeviceRequest as RumaToDeviceRequest,
+use matrix_sdk_crypto::{
+    types::requests::{
+        AnyOutgoingRequest, KeysBackupRequest as RumaKeysBackupRequest,
+        KeysQueryRequest as RumaKeysQueryRequest, OutgoingRequest as SdkOutgoingRequest,
+        OutgoingVerificationRequest as SdkOutgoingVerificationRequest,
+        RoomMessageRequest as RumaRoomMessageRequest, ToDeviceRequest as RumaToDeviceRequest,
+        UploadSigningKeysRequest as RumaSigningKeysUploadRequest,
+    },
+    CrossSigningBootstrapRequests,
 };
-use napi::bindgen_prelude::Either6;
+use napi::bindgen_prelude::{Either, Either6};
 use napi_derive::*;
 
 use crate::into_err;
@@ -47,6 +52,39 @@ impl KeysUploadRequest {
     pub fn request_type(&self) -> RequestType {
         RequestType::KeysUpload
     }
+
+    /// The JSON-encoded `device_keys` of the body, or `null` if the
+    /// device keys aren't uploaded by this request.
+    #[napi(getter)]
+    pub fn device_keys(&self) -> napi::Result<Option<String>> {
+        body_field(&self.body, "device_keys")
+    }
+
+    /// The JSON-encoded `one_time_keys` of the body, or `null` if
+    /// absent.
+    #[napi(getter)]
+    pub fn one_time_keys(&self) -> napi::Result<Option<String>> {
+        body_field(&self.body, "one_time_keys")
+    }
+
+    /// The JSON-encoded `fallback_keys` of the body, or `null` if
+    /// absent.
+    #[napi(getter)]
+    pub fn fallback_keys(&self) -> napi::Result<Option<String>> {
+        body_field(&self.body, "fallback_keys")
+    }
+}
+
+/// Get a top-level field of a JSON-encoded request body, re-encoded
+/// as JSON, or `None` if the field is absent or `null`.
+fn body_field(body: &str, field_name: &str) -> napi::Result<Option<String>> {
+    let mut body: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(body).map_err(into_err)?;
+
+    match body.remove(field_name) {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(field) => Ok(Some(serde_json::to_string(&field).map_err(into_err)?)),
+    }
 }
 
 /// Data for a request to the `/keys/query` API endpoint
@@ -225,6 +263,10 @@ pub struct KeysBackupRequest {
     #[napi(readonly)]
     pub id: String,
 
+    /// The backup version that the room keys should be uploaded to.
+    #[napi(readonly)]
+    pub version: String,
+
     /// A JSON-encoded string containing the rest of the payload: `rooms`.
     ///
     /// It represents the body of the HTTP request.
@@ -241,6 +283,72 @@ impl KeysBackupRequest {
     }
 }
 
+/// Data for a request to the `/keys/device_signing/upload` API
+/// endpoint ([specification]).
+///
+/// Publishes the public cross-signing keys of the user. This request
+/// requires user interactive authentication, and its response doesn't
+/// need to be passed back to the `OlmMachine`.
+///
+/// [specification]: https://spec.matrix.org/unstable/client-server-api/#post_matrixclientv3keysdevice_signingupload
+#[napi]
+pub struct SigningKeysUploadRequest {
+    /// The request ID.
+    #[napi(readonly)]
+    pub id: String,
+
+    /// A JSON-encoded string containing the rest of the payload:
+    /// `master_key`, `self_signing_key`, `user_signing_key`.
+    ///
+    /// It represents the body of the HTTP request.
+    #[napi(readonly)]
+    pub body: String,
+}
+
+/// The requests to send out after a bootstrap of cross-signing, see
+/// `OlmMachine.bootstrap_cross_signing`.
+///
+/// They must be sent in the order of the fields.
+#[napi(object, object_from_js = false)]
+pub struct BootstrapCrossSigningResult {
+    /// An optional request to upload the device keys, whose response
+    /// must be passed back with `OlmMachine.mark_request_as_sent`.
+    pub upload_keys_request: Option<KeysUploadRequest>,
+
+    /// The request to upload the public cross-signing keys.
+    pub upload_signing_keys_request: SigningKeysUploadRequest,
+
+    /// The request to upload the signatures of the cross-signing keys,
+    /// and of the device keys.
+    pub upload_signatures_request: SignatureUploadRequest,
+}
+
+impl TryFrom<CrossSigningBootstrapRequests> for BootstrapCrossSigningResult {
+    type Error = napi::Error;
+
+    fn try_from(requests: CrossSigningBootstrapRequests) -> Result<Self, Self::Error> {
+        let upload_keys_request = match requests.upload_keys_req {
+            Some(request) => match request.request() {
+                AnyOutgoingRequest::KeysUpload(keys_upload) => Some(KeysUploadRequest::try_from(
+                    (request.request_id().to_string(), keys_upload),
+                )?),
+                _ => {
+                    return Err(napi::Error::from_reason(
+                        "Expected a keys upload request".to_owned(),
+                    ))
+                }
+            },
+            None => None,
+        };
+
+        Ok(Self {
+            upload_keys_request,
+            upload_signing_keys_request: (&requests.upload_signing_keys_req).try_into()?,
+            upload_signatures_request: (&requests.upload_signatures_req).try_into()?,
+        })
+    }
+}
+
 macro_rules! request {
     (
         $destination_request:ident from $source_request:ident
@@ -337,7 +445,8 @@ request!(KeysClaimRequest from RumaKeysClaimRequest groups timeout { timeout.as_
 request!(ToDeviceRequest from RumaToDeviceRequest extracts event_type: string, txn_id: string and groups messages);
 request!(SignatureUploadRequest from RumaSignatureUploadRequest groups signed_keys);
 request!(RoomMessageRequest from RumaRoomMessageRequest extracts room_id: string, txn_id: string, event_type: event_type, content: json);
-request!(KeysBackupRequest from RumaKeysBackupRequest groups rooms);
+request!(KeysBackupRequest from RumaKeysBackupRequest extracts version: string and groups rooms);
+request!(SigningKeysUploadRequest from RumaSigningKeysUploadRequest groups master_key, self_signing_key, user_signing_key);
 
 pub type OutgoingRequests = Either6<
     KeysUploadRequest,
@@ -384,6 +493,29 @@ impl TryFrom<OutgoingRequest> for OutgoingRequests {
     }
 }
 
+pub(crate) struct OutgoingVerificationRequest(pub(crate) SdkOutgoingVerificationRequest);
+
+// Unlike `OutgoingRequests`, there is no type alias for this `Either`:
+// `napi` doesn't unfold type aliases into valid TypeScript definitions, so
+// the signatures have to spell it out anyway.
+impl TryFrom<OutgoingVerificationRequest> for Either<ToDeviceRequest, RoomMessageRequest> {
+    type Error = napi::Error;
+
+    fn try_from(outgoing_request: OutgoingVerificationRequest) -> Result<Self, Self::Error> {
+        let request_id = outgoing_request.0.request_id().to_string();
+
+        Ok(match &outgoing_request.0 {
+            SdkOutgoingVerificationRequest::ToDevice(request) => {
+                Either::A(ToDeviceRequest::try_from((request_id, request))?)
+            }
+
+            SdkOutgoingVerificationRequest::InRoom(request) => {
+                Either::B(RoomMessageRequest::try_from((request_id, request))?)
+            }
+        })
+    }
+}
+
 /// Represent the type of a request.
 #[napi]
 pub enum RequestType {
@@ -13,9 +13,10 @@ use matrix_sdk_common::{
     ruma::{self, api::IncomingResponse as RumaIncomingResponse},
 };
 use matrix_sdk_crypto::types::requests::AnyIncomingResponse;
+use napi::bindgen_prelude::Either6;
 use napi_derive::*;
 
-use crate::{encryption, identifiers, into_err, requests::RequestType};
+use crate::{encryption, identifiers, into_err, requests, requests::RequestType};
 
 pub(crate) fn response_from_string(body: &str) -> http::Result<http::Response<Vec<u8>>> {
     http::Response::builder().status(200).body(body.as_bytes().to_vec())
@@ -204,3 +205,45 @@ impl From<matrix_sdk_common::deserialized_responses::DecryptedRoomEvent> for Dec
         Self { event: value.event.json().to_string(), encryption_info: value.encryption_info }
     }
 }
+
+/// An event that could not be decrypted.
+#[napi]
+#[derive(Debug, Clone)]
+pub struct DecryptionError {
+    /// A human readable description of why the event could not be
+    /// decrypted.
+    #[napi(readonly)]
+    pub description: String,
+
+    /// The JSON-encoded event that could not be decrypted.
+    #[napi(readonly)]
+    pub event: String,
+}
+
+/// The result of `OlmMachine.processToDeviceBatch`.
+#[napi(object, object_from_js = false)]
+pub struct ProcessedToDeviceResult {
+    /// The JSON-encoded to-device events that have been processed,
+    /// in their decrypted form if they were encrypted.
+    pub decrypted_events: Vec<String>,
+
+    /// All the outgoing requests that need to be sent out, as returned
+    /// by `OlmMachine.outgoingRequests` once the batch has been
+    /// processed. They aren't limited to the requests caused by the
+    /// batch.
+    pub outgoing_requests: Vec<
+        // `requests::OutgoingRequests` can't be used here, `napi` doesn't
+        // unfold type aliases into valid TypeScript definitions.
+        Either6<
+            requests::KeysUploadRequest,
+            requests::KeysQueryRequest,
+            requests::KeysClaimRequest,
+            requests::ToDeviceRequest,
+            requests::SignatureUploadRequest,
+            requests::RoomMessageRequest,
+        >,
+    >,
+
+    /// The events that could not be parsed or decrypted.
+    pub errors: Vec<DecryptionError>,
+}
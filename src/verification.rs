@@ -0,0 +1,588 @@
+//! Types for the verification of devices and users.
+
+use matrix_sdk_common::ruma::{
+    api::client::keys::upload_signatures,
+    events::key::verification::VerificationMethod as RumaVerificationMethod, TransactionId,
+};
+#[cfg(feature = "qrcode")]
+use matrix_sdk_crypto::vodozemac;
+use napi::bindgen_prelude::Either;
+#[cfg(feature = "qrcode")]
+use napi::bindgen_prelude::Uint8Array;
+use napi_derive::*;
+
+use crate::{identifiers, into_err, requests};
+
+/// List of available verification methods.
+#[napi]
+#[derive(Debug)]
+pub enum VerificationMethod {
+    /// The `m.sas.v1` verification method.
+    ///
+    /// SAS means Short Authentication String.
+    SasV1 = 0,
+
+    /// The `m.qr_code.scan.v1` verification method.
+    QrCodeScanV1 = 1,
+
+    /// The `m.qr_code.show.v1` verification method.
+    QrCodeShowV1 = 2,
+
+    /// The `m.reciprocate.v1` verification method.
+    ReciprocateV1 = 3,
+}
+
+impl From<VerificationMethod> for RumaVerificationMethod {
+    fn from(value: VerificationMethod) -> Self {
+        use VerificationMethod::*;
+
+        match value {
+            SasV1 => Self::SasV1,
+            QrCodeScanV1 => Self::QrCodeScanV1,
+            QrCodeShowV1 => Self::QrCodeShowV1,
+            ReciprocateV1 => Self::ReciprocateV1,
+        }
+    }
+}
+
+impl TryFrom<RumaVerificationMethod> for VerificationMethod {
+    type Error = napi::Error;
+
+    fn try_from(value: RumaVerificationMethod) -> Result<Self, Self::Error> {
+        use RumaVerificationMethod::*;
+
+        Ok(match value {
+            SasV1 => Self::SasV1,
+            QrCodeScanV1 => Self::QrCodeScanV1,
+            QrCodeShowV1 => Self::QrCodeShowV1,
+            ReciprocateV1 => Self::ReciprocateV1,
+            _ => {
+                return Err(napi::Error::from_reason(format!(
+                    "Unknown verification method `{value}`"
+                )))
+            }
+        })
+    }
+}
+
+/// Convert a list of verification methods, skipping the ones this
+/// crate doesn't know about.
+fn known_methods(methods: Vec<RumaVerificationMethod>) -> Vec<VerificationMethod> {
+    methods.into_iter().filter_map(|method| method.try_into().ok()).collect()
+}
+
+/// An object controlling key verification requests.
+///
+/// Interactive verification flows usually start with a verification
+/// request, this object lets you send and reply to such a
+/// verification request.
+///
+/// After the initial handshake the verification flow transitions into
+/// one of the verification methods.
+#[napi]
+#[derive(Debug, Clone)]
+pub struct VerificationRequest {
+    pub(crate) inner: matrix_sdk_crypto::VerificationRequest,
+}
+
+impl From<matrix_sdk_crypto::VerificationRequest> for VerificationRequest {
+    fn from(inner: matrix_sdk_crypto::VerificationRequest) -> Self {
+        Self { inner }
+    }
+}
+
+#[napi]
+impl VerificationRequest {
+    /// Get the unique ID of this verification request.
+    #[napi(getter)]
+    pub fn flow_id(&self) -> String {
+        self.inner.flow_id().as_str().to_owned()
+    }
+
+    /// The ID of the other user that is participating in this
+    /// verification request.
+    #[napi(getter)]
+    pub fn other_user_id(&self) -> identifiers::UserId {
+        self.inner.other_user().to_owned().into()
+    }
+
+    /// The ID of the other device that is participating in this
+    /// verification, if known.
+    #[napi(getter)]
+    pub fn other_device_id(&self) -> Option<identifiers::DeviceId> {
+        self.inner.other_device_id().map(Into::into)
+    }
+
+    /// Get the room ID if the verification is happening inside a
+    /// room.
+    #[napi(getter)]
+    pub fn room_id(&self) -> Option<identifiers::RoomId> {
+        self.inner.room_id().map(|room_id| room_id.to_owned().into())
+    }
+
+    /// Has the verification request been initiated by us.
+    #[napi(getter)]
+    pub fn we_started(&self) -> bool {
+        self.inner.we_started()
+    }
+
+    /// Has the verification flow been accepted by both parties.
+    #[napi(getter)]
+    pub fn is_ready(&self) -> bool {
+        self.inner.is_ready()
+    }
+
+    /// Is the verification request passive, i.e. has it been
+    /// answered on another device of ours.
+    #[napi(getter)]
+    pub fn is_passive(&self) -> bool {
+        self.inner.is_passive()
+    }
+
+    /// Has the verification flow been completed.
+    #[napi(getter)]
+    pub fn is_done(&self) -> bool {
+        self.inner.is_done()
+    }
+
+    /// Has the verification flow been cancelled.
+    #[napi(getter)]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+
+    /// Get the supported verification methods of the other side.
+    ///
+    /// Will be present only if the other side requested the
+    /// verification or if we're in the ready state.
+    #[napi(getter)]
+    pub fn their_supported_methods(&self) -> Option<Vec<VerificationMethod>> {
+        self.inner.their_supported_methods().map(known_methods)
+    }
+
+    /// Get our own supported verification methods that we advertised.
+    ///
+    /// Will be present only if we requested the verification or if
+    /// we're in the ready state.
+    #[napi(getter)]
+    pub fn our_supported_methods(&self) -> Option<Vec<VerificationMethod>> {
+        self.inner.our_supported_methods().map(known_methods)
+    }
+
+    /// Accept the verification request with the given verification
+    /// methods.
+    ///
+    /// Returns the request that notifies the other side of the
+    /// acceptance, or `null` if the verification request cannot be
+    /// accepted, e.g. because we started it. It needs to be sent out
+    /// and its response passed back to the `OlmMachine` using
+    /// `mark_request_as_sent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `methods`, the verification methods we want to use.
+    #[napi]
+    pub fn accept(
+        &self,
+        methods: Vec<VerificationMethod>,
+    ) -> napi::Result<Option<Either<requests::ToDeviceRequest, requests::RoomMessageRequest>>> {
+        self.inner
+            .accept_with_methods(methods.into_iter().map(Into::into).collect())
+            .map(requests::OutgoingVerificationRequest)
+            .map(TryFrom::try_from)
+            .transpose()
+    }
+}
+
+/// The result of accepting a verification request.
+#[napi(object, object_from_js = false)]
+pub struct AcceptedVerificationRequest {
+    /// The accepted verification request.
+    pub request: VerificationRequest,
+
+    /// The request that notifies the other side of the acceptance, if
+    /// any. It needs to be sent out and its response passed back to the
+    /// `OlmMachine` using `mark_request_as_sent`.
+    pub outgoing_request: Option<Either<requests::ToDeviceRequest, requests::RoomMessageRequest>>,
+}
+
+/// The result of requesting a verification.
+#[napi(object, object_from_js = false)]
+pub struct RequestedVerification {
+    /// The created verification request.
+    pub request: VerificationRequest,
+
+    /// The request that notifies the other side of the verification
+    /// request. It needs to be sent out and its response passed back to
+    /// the `OlmMachine` using `mark_request_as_sent`.
+    pub outgoing_request: Either<requests::ToDeviceRequest, requests::RoomMessageRequest>,
+}
+
+/// Short authentication string object.
+#[napi]
+#[derive(Debug, Clone)]
+pub struct Sas {
+    pub(crate) inner: matrix_sdk_crypto::Sas,
+}
+
+impl From<matrix_sdk_crypto::Sas> for Sas {
+    fn from(inner: matrix_sdk_crypto::Sas) -> Self {
+        Self { inner }
+    }
+}
+
+#[napi]
+impl Sas {
+    /// Get the unique ID that identifies this SAS verification flow.
+    #[napi(getter)]
+    pub fn flow_id(&self) -> String {
+        self.inner.flow_id().as_str().to_owned()
+    }
+
+    /// Get the user ID of the other side.
+    #[napi(getter)]
+    pub fn other_user_id(&self) -> identifiers::UserId {
+        self.inner.other_user_id().to_owned().into()
+    }
+
+    /// Get the device ID of the other side.
+    #[napi(getter)]
+    pub fn other_device_id(&self) -> identifiers::DeviceId {
+        self.inner.other_device_id().to_owned().into()
+    }
+
+    /// Get the room ID if the verification is happening inside a
+    /// room.
+    #[napi(getter)]
+    pub fn room_id(&self) -> Option<identifiers::RoomId> {
+        self.inner.room_id().map(|room_id| room_id.to_owned().into())
+    }
+
+    /// Did we initiate the verification flow.
+    #[napi(getter)]
+    pub fn we_started(&self) -> bool {
+        self.inner.we_started()
+    }
+
+    /// Does this verification flow support displaying emoji for the
+    /// short authentication string.
+    #[napi(getter)]
+    pub fn supports_emoji(&self) -> bool {
+        self.inner.supports_emoji()
+    }
+
+    /// Has the verification been accepted by both parties.
+    #[napi(getter)]
+    pub fn has_been_accepted(&self) -> bool {
+        self.inner.has_been_accepted()
+    }
+
+    /// Is the SAS flow done.
+    #[napi(getter)]
+    pub fn is_done(&self) -> bool {
+        self.inner.is_done()
+    }
+
+    /// Is the SAS flow cancelled.
+    #[napi(getter)]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// The result of starting a SAS verification.
+#[napi(object, object_from_js = false)]
+pub struct StartedSas {
+    /// The started SAS verification.
+    pub sas: Sas,
+
+    /// The request that notifies the other side of the verification.
+    /// It needs to be sent out and its response passed back to the
+    /// `OlmMachine` using `mark_request_as_sent`.
+    pub outgoing_request: requests::ToDeviceRequest,
+}
+
+/// An emoji that is used for interactive verification using a short
+/// auth string.
+///
+/// This will contain a single emoji and description from the list of
+/// emojis from the [spec].
+///
+/// [spec]: https://spec.matrix.org/unstable/client-server-api/#sas-method-emoji
+#[napi(object)]
+#[derive(Debug)]
+pub struct Emoji {
+    /// The emoji symbol that represents a part of the short auth
+    /// string, for example: 🐶
+    pub symbol: String,
+
+    /// The description of the emoji, for example “Dog”.
+    pub description: String,
+}
+
+impl From<matrix_sdk_crypto::Emoji> for Emoji {
+    fn from(emoji: matrix_sdk_crypto::Emoji) -> Self {
+        Self { symbol: emoji.symbol.to_owned(), description: emoji.description.to_owned() }
+    }
+}
+
+/// The result of confirming a verification flow, e.g. that the short
+/// auth strings of a SAS verification match.
+#[napi(object, object_from_js = false)]
+pub struct ConfirmedVerification {
+    /// The requests that notify the other side of the confirmation.
+    /// They need to be sent out and their responses passed back to the
+    /// `OlmMachine` using `mark_request_as_sent`.
+    pub outgoing_requests: Vec<Either<requests::ToDeviceRequest, requests::RoomMessageRequest>>,
+
+    /// The request that uploads the signatures of the verified device
+    /// or user, if any. It needs to be sent out too.
+    pub signature_upload_request: Option<requests::SignatureUploadRequest>,
+}
+
+impl ConfirmedVerification {
+    pub(crate) fn new(
+        outgoing_requests: Vec<matrix_sdk_crypto::types::requests::OutgoingVerificationRequest>,
+        signature_upload_request: Option<upload_signatures::v3::Request>,
+    ) -> napi::Result<Self> {
+        Ok(Self {
+            outgoing_requests: outgoing_requests
+                .into_iter()
+                .map(requests::OutgoingVerificationRequest)
+                .map(TryFrom::try_from)
+                .collect::<napi::Result<_>>()?,
+            signature_upload_request: signature_upload_request
+                .map(|request| {
+                    requests::SignatureUploadRequest::try_from((
+                        TransactionId::new().to_string(),
+                        &request,
+                    ))
+                })
+                .transpose()?,
+        })
+    }
+}
+
+/// The type of a `Verification`, i.e. `'sas'` or `'qr'`.
+#[napi(string_enum = "lowercase")]
+#[derive(Debug)]
+pub enum VerificationType {
+    /// A SAS verification, see `Sas`.
+    Sas,
+
+    /// A QR code verification, see `QrCode`.
+    Qr,
+}
+
+/// An interactive verification flow, started from a verification
+/// request or directly, using one of the verification methods.
+#[napi]
+#[derive(Debug, Clone)]
+pub struct Verification {
+    pub(crate) inner: matrix_sdk_crypto::Verification,
+}
+
+impl From<matrix_sdk_crypto::Verification> for Verification {
+    fn from(inner: matrix_sdk_crypto::Verification) -> Self {
+        Self { inner }
+    }
+}
+
+#[napi]
+impl Verification {
+    /// Get the type of the verification, which tells which
+    /// verification method is used.
+    #[napi(getter, js_name = "type")]
+    pub fn verification_type(&self) -> napi::Result<VerificationType> {
+        match &self.inner {
+            matrix_sdk_crypto::Verification::SasV1(_) => Ok(VerificationType::Sas),
+            #[cfg(feature = "qrcode")]
+            matrix_sdk_crypto::Verification::QrV1(_) => Ok(VerificationType::Qr),
+            _ => Err(napi::Error::from_reason("Unsupported verification type")),
+        }
+    }
+
+    /// Get the unique ID that identifies this verification flow.
+    #[napi(getter)]
+    pub fn flow_id(&self) -> String {
+        self.inner.flow_id().to_owned()
+    }
+
+    /// Get the user ID of the other side.
+    #[napi(getter)]
+    pub fn other_user_id(&self) -> identifiers::UserId {
+        self.inner.other_user().to_owned().into()
+    }
+
+    /// Is the verification flow done.
+    #[napi(getter)]
+    pub fn is_done(&self) -> bool {
+        self.inner.is_done()
+    }
+
+    /// Is the verification flow cancelled.
+    #[napi(getter)]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+
+    /// Get the code of the cancellation, e.g. `m.user`, if the
+    /// verification flow has been cancelled.
+    #[napi(getter)]
+    pub fn cancel_code(&self) -> Option<String> {
+        let cancel_info = match &self.inner {
+            matrix_sdk_crypto::Verification::SasV1(sas) => sas.cancel_info(),
+            #[cfg(feature = "qrcode")]
+            matrix_sdk_crypto::Verification::QrV1(qr) => qr.cancel_info(),
+            _ => None,
+        };
+
+        cancel_info.map(|cancel_info| cancel_info.cancel_code().as_str().to_owned())
+    }
+
+    /// Accept the verification flow if it has been started by the
+    /// other side.
+    ///
+    /// Returns the request that notifies the other side of the
+    /// acceptance, or `null` if there is nothing to accept, e.g.
+    /// because we started the verification, or because QR code
+    /// verifications don't need to be accepted. It needs to be sent out
+    /// and its response passed back to the `OlmMachine` using
+    /// `mark_request_as_sent`.
+    #[napi]
+    pub fn accept(
+        &self,
+    ) -> napi::Result<Option<Either<requests::ToDeviceRequest, requests::RoomMessageRequest>>> {
+        let request = match &self.inner {
+            matrix_sdk_crypto::Verification::SasV1(sas) => sas.accept(),
+            _ => None,
+        };
+
+        request.map(requests::OutgoingVerificationRequest).map(TryFrom::try_from).transpose()
+    }
+
+    /// Confirm the verification flow, i.e. that the short auth strings
+    /// match for SAS verifications, or that the other side has scanned
+    /// our QR code for QR code verifications.
+    ///
+    /// QR code verifications never return a signature upload request.
+    #[napi]
+    pub async fn confirm(&self) -> napi::Result<ConfirmedVerification> {
+        match &self.inner {
+            matrix_sdk_crypto::Verification::SasV1(sas) => {
+                let (outgoing_requests, signature_upload_request) =
+                    sas.confirm().await.map_err(into_err)?;
+
+                ConfirmedVerification::new(outgoing_requests, signature_upload_request)
+            }
+            #[cfg(feature = "qrcode")]
+            matrix_sdk_crypto::Verification::QrV1(qr) => {
+                ConfirmedVerification::new(qr.confirm_scanning().into_iter().collect(), None)
+            }
+            _ => Err(napi::Error::from_reason("Unsupported verification type")),
+        }
+    }
+
+    /// Cancel the verification flow.
+    ///
+    /// Returns the request that notifies the other side of the
+    /// cancellation, or `null` if the verification is already done or
+    /// cancelled. It needs to be sent out and its response passed back
+    /// to the `OlmMachine` using `mark_request_as_sent`.
+    #[napi]
+    pub fn cancel(
+        &self,
+    ) -> napi::Result<Option<Either<requests::ToDeviceRequest, requests::RoomMessageRequest>>> {
+        let request = match &self.inner {
+            matrix_sdk_crypto::Verification::SasV1(sas) => sas.cancel(),
+            #[cfg(feature = "qrcode")]
+            matrix_sdk_crypto::Verification::QrV1(qr) => qr.cancel(),
+            _ => None,
+        };
+
+        request.map(requests::OutgoingVerificationRequest).map(TryFrom::try_from).transpose()
+    }
+}
+
+/// A QR code verification flow.
+#[cfg(feature = "qrcode")]
+#[napi]
+#[derive(Debug, Clone)]
+pub struct QrCode {
+    pub(crate) inner: matrix_sdk_crypto::QrVerification,
+}
+
+#[cfg(feature = "qrcode")]
+impl From<matrix_sdk_crypto::QrVerification> for QrCode {
+    fn from(inner: matrix_sdk_crypto::QrVerification) -> Self {
+        Self { inner }
+    }
+}
+
+#[cfg(feature = "qrcode")]
+#[napi]
+impl QrCode {
+    /// Get the unique ID that identifies this QR code verification
+    /// flow.
+    #[napi(getter)]
+    pub fn flow_id(&self) -> String {
+        self.inner.flow_id().as_str().to_owned()
+    }
+
+    /// Get the user ID of the other side.
+    #[napi(getter)]
+    pub fn other_user_id(&self) -> identifiers::UserId {
+        self.inner.other_user_id().to_owned().into()
+    }
+
+    /// Get the device ID of the other side.
+    #[napi(getter)]
+    pub fn other_device_id(&self) -> identifiers::DeviceId {
+        self.inner.other_device_id().to_owned().into()
+    }
+
+    /// Has the QR code been scanned by the other side.
+    #[napi(getter)]
+    pub fn has_been_scanned(&self) -> bool {
+        self.inner.has_been_scanned()
+    }
+
+    /// Is the QR code verification flow done.
+    #[napi(getter)]
+    pub fn is_done(&self) -> bool {
+        self.inner.is_done()
+    }
+
+    /// Is the QR code verification flow cancelled.
+    #[napi(getter)]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+
+    /// Encode the QR code into bytes, to be rendered as a QR code
+    /// image by the client.
+    #[napi]
+    pub fn to_bytes(&self) -> napi::Result<Uint8Array> {
+        Ok(Uint8Array::new(self.inner.to_bytes().map_err(into_err)?))
+    }
+
+    /// Encode the QR code into bytes, and return them as an unpadded
+    /// base64 string.
+    #[napi]
+    pub fn to_base64(&self) -> napi::Result<String> {
+        Ok(vodozemac::base64_encode(self.inner.to_bytes().map_err(into_err)?))
+    }
+}
+
+/// The result of scanning the QR code of the other side.
+#[cfg(feature = "qrcode")]
+#[napi(object, object_from_js = false)]
+pub struct ScanResult {
+    /// The QR code verification flow started by the scan.
+    pub qr: QrCode,
+
+    /// The request that notifies the other side that we scanned their
+    /// QR code, if any. It needs to be sent out and its response
+    /// passed back to the `OlmMachine` using `mark_request_as_sent`.
+    pub outgoing_request: Option<Either<requests::ToDeviceRequest, requests::RoomMessageRequest>>,
+}
@@ -1,5 +1,6 @@
 const {
     OlmMachine,
+    OlmMachineLock,
     UserId,
     DeviceId,
     DeviceKeyId,
@@ -8,28 +9,64 @@ const {
     RequestType,
     KeysUploadRequest,
     KeysQueryRequest,
+    KeysClaimRequest,
     EncryptionSettings,
     DecryptedRoomEvent,
     CrossSigningStatus,
     MaybeSignature,
     ToDeviceRequest,
+    SignatureUploadRequest,
+    SigningKeysUploadRequest,
     ShieldColor,
     StoreType,
     Versions,
     getVersions,
     SignatureState,
     BackupDecryptionKey,
+    DecryptionError,
+    LocalTrust,
+    Device,
+    UserDevices,
+    UserIdentity,
+    OwnUserIdentity,
+    IdentityConflictAction,
+    VerificationMethod,
+    VerificationRequest,
+    Sas,
+    QrCode,
+    Verification,
+    VerificationType,
 } = require("../");
 const path = require("path");
 const os = require("os");
 const fs = require("fs/promises");
 
+// The QR code verification is only available with the `qrcode` feature.
+const qrCodeSupported = typeof OlmMachine.prototype.startQrVerification === "function";
+
 describe("StoreType", () => {
     test("has the correct variant values", () => {
         expect(StoreType.Sqlite).toStrictEqual(0);
     });
 });
 
+describe("LocalTrust", () => {
+    test("has the correct variant values", () => {
+        expect(LocalTrust.Verified).toStrictEqual(0);
+        expect(LocalTrust.BlackListed).toStrictEqual(1);
+        expect(LocalTrust.Ignored).toStrictEqual(2);
+        expect(LocalTrust.Unset).toStrictEqual(3);
+    });
+});
+
+describe("IdentityConflictAction", () => {
+    test("has the correct variant values", () => {
+        expect(IdentityConflictAction.PinNew).toStrictEqual(0);
+        expect(IdentityConflictAction.Reject).toStrictEqual(1);
+        expect(IdentityConflictAction.Ignore).toStrictEqual(2);
+    });
+});
+
 describe("Versions", () => {
     test("can find out the crate versions", async () => {
         const versions = getVersions();
@@ -114,6 +151,41 @@ describe(OlmMachine.name, () => {
         m2.close();
     });
 
+    test("can check if a store is initialised", async () => {
+        const temp_directory = await fs.mkdtemp(path.join(os.tmpdir(), "matrix-sdk-crypto--"));
+        expect(await OlmMachine.isStoreInitialised(temp_directory, "hello")).toStrictEqual(false);
+
+        const m = await OlmMachine.initialize(user, device, temp_directory, "hello");
+        m.close();
+
+        expect(await OlmMachine.isStoreInitialised(temp_directory, "hello")).toStrictEqual(true);
+    });
+
+    test("can lock the store", async () => {
+        const temp_directory = await fs.mkdtemp(path.join(os.tmpdir(), "matrix-sdk-crypto--"));
+        const m1 = await OlmMachine.initialize(user, device, temp_directory, "hello");
+        const m2 = await OlmMachine.initialize(user, device, temp_directory, "hello");
+
+        const lock = await m1.lockForCurrentThread();
+        expect(lock).toBeInstanceOf(OlmMachineLock);
+
+        let locked = false;
+        const otherLock = m2.lockForCurrentThread().then((lock) => {
+            locked = true;
+            return lock;
+        });
+
+        await new Promise((resolve) => setTimeout(resolve, 200));
+        expect(locked).toStrictEqual(false);
+
+        lock.release();
+        (await otherLock).release();
+        expect(locked).toStrictEqual(true);
+
+        m1.close();
+        m2.close();
+    });
+
     test("can read user ID", async () => {
         expect((await machine()).userId.toString()).toStrictEqual(user.toString());
     });
@@ -129,6 +201,24 @@ describe(OlmMachine.name, () => {
         expect(identityKeys.curve25519.toBase64()).toMatch(/^[A-Za-z0-9+/]+$/);
     });
 
+    test("can read the device fingerprint", async () => {
+        const m = await machine();
+        const fingerprint = await m.deviceFingerprint();
+
+        expect(fingerprint).toMatch(/^([A-Za-z0-9+/]{4} )*[A-Za-z0-9+/]{1,4}$/);
+        expect(fingerprint.replaceAll(" ", "")).toStrictEqual(m.identityKeys.ed25519.toBase64());
+    });
+
+    test("can read account info", async () => {
+        const m = await machine();
+        const accountInfo = await m.getAccountInfo();
+
+        expect(accountInfo.olmAccountIsShared).toStrictEqual(false);
+        expect(accountInfo.oneTimeKeyCount).toStrictEqual(0);
+        expect(accountInfo.fallbackKeyGenerated).toStrictEqual(false);
+        expect(accountInfo.deviceKeyFingerprint).toStrictEqual(m.identityKeys.ed25519.toBase64());
+    });
+
     test("can receive sync changes", async () => {
         const m = await machine();
         const toDeviceEvents = JSON.stringify([]);
@@ -143,6 +233,85 @@ describe(OlmMachine.name, () => {
         expect(receiveSyncChanges).toEqual([[], []]);
     });
 
+    test("can handle a withheld room key", async () => {
+        const m = await machine();
+        const content = {
+            algorithm: "m.megolm.v1.aes-sha2",
+            code: "m.unverified",
+            reason: "Device not verified",
+            room_id: room.toString(),
+            sender_key: "boYjDpaC+7NkECQEeMh5dC+I1+AfriX0VXG2UV7EUQo",
+            session_id: "SESSIONID",
+        };
+
+        expect(await m.getWithheldInfo(room, "SESSIONID")).toBeNull();
+
+        await m.handleRoomKeyWithheld(
+            JSON.stringify({ type: "m.room_key.withheld", sender: "@example:localhost", content }),
+        );
+
+        const withheld = JSON.parse(await m.getWithheldInfo(room, "SESSIONID"));
+        expect(withheld.code).toStrictEqual("m.unverified");
+        expect(withheld.session_id).toStrictEqual("SESSIONID");
+
+        await expect(
+            m.handleRoomKeyWithheld(JSON.stringify({ type: "m.dummy", sender: "@example:localhost", content: {} })),
+        ).rejects.toThrow();
+    });
+
+    test("can handle device list changes", async () => {
+        const m = await machine();
+        const bob = new UserId("@bob:example.org");
+
+        await m.updateTrackedUsers([bob]);
+
+        const requests = await m.handleSyncDeviceChanges([bob], []);
+
+        expect(requests).toHaveLength(1);
+        expect(requests[0]).toBeInstanceOf(KeysQueryRequest);
+        expect(Object.keys(JSON.parse(requests[0].body).device_keys)).toContain(bob.toString());
+    });
+
+    test("can process one-time key counts", async () => {
+        const m = await machine();
+
+        expect(await m.processSyncOneTimeKeyCounts({ signed_curve25519: 42 })).toStrictEqual(undefined);
+        expect((await m.getAccountInfo()).oneTimeKeyCount).toStrictEqual(42);
+    });
+
+    test("can get the receive count", async () => {
+        const m = await machine();
+
+        expect(await m.getReceiveCount()).toStrictEqual({ olmMessages: 0, megolmMessages: 0 });
+    });
+
+    test("can get the send count", async () => {
+        const m = await machine();
+
+        expect(await m.getSendCount()).toStrictEqual({ toDeviceMessages: 0, roomMessages: 0 });
+    });
+
+    test("can process unused fallback key types", async () => {
+        const m = await machine();
+
+        expect(await m.processUnusedFallbackKeyTypes([])).toStrictEqual(undefined);
+        expect((await m.getAccountInfo()).fallbackKeyGenerated).toStrictEqual(true);
+    });
+
+    test("can generate a fallback key if needed", async () => {
+        const m = await machine();
+
+        expect(await m.generateFallbackKeyIfNeeded()).toBeNull();
+
+        await m.processUnusedFallbackKeyTypes([]);
+
+        const request = await m.generateFallbackKeyIfNeeded();
+
+        expect(request).toBeInstanceOf(KeysUploadRequest);
+        expect(request.type).toStrictEqual(RequestType.KeysUpload);
+        expect(Object.keys(JSON.parse(request.fallbackKeys))).toHaveLength(1);
+    });
+
     test("can get the outgoing requests that need to be sent out", async () => {
         const m = await machine();
         const toDeviceEvents = JSON.stringify([]);
@@ -168,6 +337,10 @@ describe(OlmMachine.name, () => {
             const body = JSON.parse(outgoingRequests[0].body);
             expect(body.device_keys).toBeDefined();
             expect(body.one_time_keys).toBeDefined();
+
+            expect(JSON.parse(outgoingRequests[0].deviceKeys)).toStrictEqual(body.device_keys);
+            expect(JSON.parse(outgoingRequests[0].oneTimeKeys)).toStrictEqual(body.one_time_keys);
+            expect(JSON.parse(outgoingRequests[0].fallbackKeys)).toStrictEqual(body.fallback_keys);
         }
 
         {
@@ -181,6 +354,38 @@ describe(OlmMachine.name, () => {
         }
     });
 
+    test("can force the upload of the device keys", async () => {
+        const m = await machine();
+        const request = await m.forceSendDeviceKeys();
+
+        expect(request).toBeInstanceOf(KeysUploadRequest);
+        expect(request.id).toBeDefined();
+        expect(request.type).toStrictEqual(RequestType.KeysUpload);
+
+        const body = JSON.parse(request.body);
+        expect(body.device_keys.device_id).toStrictEqual(device.toString());
+        expect(body.device_keys.user_id).toStrictEqual(user.toString());
+        expect(body.one_time_keys).toStrictEqual({});
+
+        expect(await m.markRequestAsSent(request.id, request.type, '{"one_time_key_counts":{}}')).toStrictEqual(true);
+        expect((await m.forceSendDeviceKeys()).body).toStrictEqual(request.body);
+    });
+
+    test("can process a batch of to-device events", async () => {
+        const m = await machine();
+        const dummy = JSON.stringify({ type: "m.dummy", sender: "@bob:example.org", content: {} });
+
+        const result = await m.processToDeviceBatch(["{invalid", dummy]);
+
+        expect(result.decryptedEvents).toHaveLength(1);
+        expect(JSON.parse(result.decryptedEvents[0]).type).toStrictEqual("m.dummy");
+        expect(result.outgoingRequests).toHaveLength(2);
+        expect(result.errors).toHaveLength(1);
+        expect(result.errors[0]).toBeInstanceOf(DecryptionError);
+        expect(result.errors[0].event).toStrictEqual("{invalid");
+        expect(result.errors[0].description).toBeDefined();
+    });
+
     describe("setup workflow to mark requests as sent", () => {
         let m;
         let ougoingRequests;
@@ -261,6 +466,200 @@ describe(OlmMachine.name, () => {
             m = await machine(user, device);
         });
 
+        // derived from https://github.com/matrix-org/matrix-rust-sdk/blob/7f49618d350fab66b7e1dc4eaf64ec25ceafd658/benchmarks/benches/crypto_bench/keys_query.json
+        const keysQueryResponse = JSON.stringify({
+            device_keys: {
+                "@example:localhost": {
+                    AFGUOBTZWM: {
+                        algorithms: ["m.olm.v1.curve25519-aes-sha2", "m.megolm.v1.aes-sha2"],
+                        device_id: "AFGUOBTZWM",
+                        keys: {
+                            "curve25519:AFGUOBTZWM": "boYjDpaC+7NkECQEeMh5dC+I1+AfriX0VXG2UV7EUQo",
+                            "ed25519:AFGUOBTZWM": "NayrMQ33ObqMRqz6R9GosmHdT6HQ6b/RX/3QlZ2yiec",
+                        },
+                        signatures: {
+                            "@example:localhost": {
+                                "ed25519:AFGUOBTZWM":
+                                    "RoSWvru1jj6fs2arnTedWsyIyBmKHMdOu7r9gDi0BZ61h9SbCK2zLXzuJ9ZFLao2VvA0yEd7CASCmDHDLYpXCA",
+                            },
+                        },
+                        user_id: "@example:localhost",
+                        unsigned: {
+                            device_display_name: "rust-sdk",
+                        },
+                    },
+                },
+            },
+            failures: {},
+            master_keys: {
+                "@example:localhost": {
+                    user_id: "@example:localhost",
+                    usage: ["master"],
+                    keys: {
+                        "ed25519:n2lpJGx0LiKnuNE1IucZP3QExrD4SeRP0veBHPe3XUU":
+                            "n2lpJGx0LiKnuNE1IucZP3QExrD4SeRP0veBHPe3XUU",
+                    },
+                    signatures: {
+                        "@example:localhost": {
+                            "ed25519:TCSJXPWGVS":
+                                "+j9G3L41I1fe0++wwusTTQvbboYW0yDtRWUEujhwZz4MAltjLSfJvY0hxhnz+wHHmuEXvQDen39XOpr1p29sAg",
+                        },
+                    },
+                },
+            },
+            self_signing_keys: {
+                "@example:localhost": {
+                    user_id: "@example:localhost",
+                    usage: ["self_signing"],
+                    keys: {
+                        "ed25519:kQXOuy639Yt47mvNTdrIluoC6DMvfbZLYbxAmwiDyhI":
+                            "kQXOuy639Yt47mvNTdrIluoC6DMvfbZLYbxAmwiDyhI",
+                    },
+                    signatures: {
+                        "@example:localhost": {
+                            "ed25519:n2lpJGx0LiKnuNE1IucZP3QExrD4SeRP0veBHPe3XUU":
+                                "q32ifix/qyRpvmegw2BEJklwoBCAJldDNkcX+fp+lBA4Rpyqtycxge6BA4hcJdxYsy3oV0IHRuugS8rJMMFyAA",
+                        },
+                    },
+                },
+            },
+            user_signing_keys: {
+                "@example:localhost": {
+                    user_id: "@example:localhost",
+                    usage: ["user_signing"],
+                    keys: {
+                        "ed25519:g4ED07Fnqf3GzVWNN1pZ0IFrPQVdqQf+PYoJNH4eE0s":
+                            "g4ED07Fnqf3GzVWNN1pZ0IFrPQVdqQf+PYoJNH4eE0s",
+                    },
+                    signatures: {
+                        "@example:localhost": {
+                            "ed25519:n2lpJGx0LiKnuNE1IucZP3QExrD4SeRP0veBHPe3XUU":
+                                "nKQu8alQKDefNbZz9luYPcNj+Z+ouQSot4fU/A23ELl1xrI06QVBku/SmDx0sIW1ytso0Cqwy1a+3PzCa1XABg",
+                        },
+                    },
+                },
+            },
+        });
+
+        test("can process the initial device list", async () => {
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            const other_user = new UserId("@example:localhost");
+            const other_device = new DeviceId("AFGUOBTZWM");
+
+            expect(await m2.localTrustForDevice(other_user, other_device)).toBeNull();
+            expect(await m2.processInitialDeviceList(keysQueryResponse)).toStrictEqual(undefined);
+            expect(await m2.localTrustForDevice(other_user, other_device)).toStrictEqual(LocalTrust.Unset);
+        });
+
+        test("can listen to identity changes", async () => {
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            const other_user = new UserId("@example:localhost");
+
+            const changes = [];
+            m2.onIdentityChange((userId, oldKeys, newKeys) => changes.push({ userId, oldKeys, newKeys }));
+
+            // Identities seen for the first time aren't reported.
+            await m2.processInitialDeviceList(keysQueryResponse);
+            await new Promise((resolve) => setTimeout(resolve, 100));
+            expect(changes).toStrictEqual([]);
+
+            // The keys of the identities of the tracked users are known to listeners registered later on.
+            const lateChanges = [];
+            await m2.updateTrackedUsers([other_user]);
+            m2.onIdentityChange((userId, oldKeys) => lateChanges.push(oldKeys));
+
+            // Replace the identity of the other user by a new one.
+            const other = await machine(other_user, new DeviceId("AFGUOBTZWM"));
+            const newIdentity = JSON.parse((await other.bootstrapCrossSigning(true)).uploadSigningKeysRequest.body);
+            await m2.processInitialDeviceList(
+                JSON.stringify({
+                    device_keys: {},
+                    master_keys: { [other_user.toString()]: newIdentity.master_key },
+                    self_signing_keys: { [other_user.toString()]: newIdentity.self_signing_key },
+                }),
+            );
+            await new Promise((resolve) => setTimeout(resolve, 100));
+
+            expect(changes).toHaveLength(1);
+            const [{ userId, oldKeys, newKeys }] = changes;
+            expect(userId.toString()).toStrictEqual(other_user.toString());
+            expect(Object.values(JSON.parse(oldKeys.masterKey).keys)).toStrictEqual([
+                "n2lpJGx0LiKnuNE1IucZP3QExrD4SeRP0veBHPe3XUU",
+            ]);
+            expect(Object.values(JSON.parse(oldKeys.selfSigningKey).keys)).toStrictEqual([
+                "kQXOuy639Yt47mvNTdrIluoC6DMvfbZLYbxAmwiDyhI",
+            ]);
+            expect(JSON.parse(newKeys.masterKey).keys).toStrictEqual(newIdentity.master_key.keys);
+            expect(JSON.parse(newKeys.selfSigningKey).keys).toStrictEqual(newIdentity.self_signing_key.keys);
+
+            expect(lateChanges).toHaveLength(1);
+            expect(lateChanges[0].masterKey).toStrictEqual(oldKeys.masterKey);
+
+            // Pinning the new identity isn't a change of the master key.
+            await (await m2.getIdentity(other_user)).pinCurrentIdentity();
+            await new Promise((resolve) => setTimeout(resolve, 100));
+            expect(changes).toHaveLength(1);
+        });
+
+        test("can get the users whose identity has changed", async () => {
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+
+            await m2.processInitialDeviceList(keysQueryResponse);
+            await new Promise((resolve) => setTimeout(resolve, 100));
+
+            expect(await m2.getIdentityChangedUsers()).toStrictEqual([]);
+
+            await (await m2.getIdentity(new UserId("@example:localhost"))).pinCurrentIdentity();
+            await new Promise((resolve) => setTimeout(resolve, 100));
+
+            const changedUserIds = await m2.getIdentityChangedUsers();
+            expect(changedUserIds.map((userId) => userId.toString())).toStrictEqual(["@example:localhost"]);
+
+            await m2.clearIdentityChangedUsers();
+            expect(await m2.getIdentityChangedUsers()).toStrictEqual([]);
+        });
+
+        test("can listen to new devices", async () => {
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+
+            const newDevices = [];
+            m2.onNewDevice((userId, deviceId) => newDevices.push([userId.toString(), deviceId.toString()]));
+
+            await m2.processInitialDeviceList(keysQueryResponse);
+            await new Promise((resolve) => setTimeout(resolve, 100));
+
+            expect(newDevices).toStrictEqual([["@example:localhost", "AFGUOBTZWM"]]);
+        });
+
+        test("can get the users who have new devices", async () => {
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            expect(await m2.newDeviceUsers()).toStrictEqual([]);
+
+            await m2.processInitialDeviceList(keysQueryResponse);
+            await new Promise((resolve) => setTimeout(resolve, 100));
+
+            const userIds = await m2.newDeviceUsers();
+            expect(userIds.map((userId) => userId.toString())).toStrictEqual(["@example:localhost"]);
+
+            await m2.clearNewDeviceUsers();
+            expect(await m2.newDeviceUsers()).toStrictEqual([]);
+        });
+
+        test("can listen to deleted devices", async () => {
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            await m2.processInitialDeviceList(keysQueryResponse);
+
+            const deletedDevices = [];
+            m2.onDeviceDeleted((userId, deviceId) => deletedDevices.push([userId.toString(), deviceId.toString()]));
+
+            const response = JSON.parse(keysQueryResponse);
+            response.device_keys["@example:localhost"] = {};
+            await m2.processInitialDeviceList(JSON.stringify(response));
+            await new Promise((resolve) => setTimeout(resolve, 100));
+
+            expect(deletedDevices).toStrictEqual([["@example:localhost", "AFGUOBTZWM"]]);
+        });
+
         test("can pass keysquery and keysclaim requests directly", async () => {
             {
                 // derived from https://github.com/matrix-org/matrix-rust-sdk/blob/7f49618d350fab66b7e1dc4eaf64ec25ceafd658/benchmarks/benches/crypto_bench/keys_query.json
@@ -364,6 +763,479 @@ describe(OlmMachine.name, () => {
             }
         });
 
+        test("can export Olm sessions", async () => {
+            await m.updateTrackedUsers([new UserId("@example:localhost")]);
+            const exported = JSON.parse(await m.exportOlmSessionsForBackup("passphrase"));
+
+            // The pickles hold the private state of the ratchets, so they are encrypted.
+            expect(Object.keys(exported).sort()).toStrictEqual(["cipher", "payload"]);
+        });
+
+        test("can import Olm sessions", async () => {
+            const other_user = new UserId("@example:localhost");
+            const keysClaimResponse = JSON.stringify({
+                one_time_keys: {
+                    "@example:localhost": {
+                        AFGUOBTZWM: {
+                            "signed_curve25519:AAAABQ": {
+                                key: "9IGouMnkB6c6HOd4xUsNv4i3Dulb4IS96TzDordzOws",
+                                signatures: {
+                                    "@example:localhost": {
+                                        "ed25519:AFGUOBTZWM":
+                                            "2bvUbbmJegrV0eVP/vcJKuIWC3kud+V8+C0dZtg4dVovOSJdTP/iF36tQn2bh5+rb9xLlSeztXBdhy4c+LiOAg",
+                                    },
+                                },
+                            },
+                        },
+                    },
+                },
+                failures: {},
+            });
+
+            // The sessions are imported into a copy of the store taken before they were created.
+            const directory = await fs.mkdtemp(path.join(os.tmpdir(), "matrix-sdk-crypto--"));
+            const copy = await fs.mkdtemp(path.join(os.tmpdir(), "matrix-sdk-crypto--"));
+            (await OlmMachine.initialize(user, device, directory)).close();
+            await fs.cp(directory, copy, { recursive: true });
+
+            const m1 = await OlmMachine.initialize(user, device, directory);
+            await m1.updateTrackedUsers([other_user]);
+            await m1.markRequestAsSent("foo", RequestType.KeysQuery, keysQueryResponse);
+            await m1.markRequestAsSent("bar", RequestType.KeysClaim, keysClaimResponse);
+            const sessions = await m1.exportOlmSessionsForBackup("passphrase");
+            m1.close();
+
+            const m2 = await OlmMachine.initialize(user, device, copy);
+
+            // The sessions with untracked users are skipped.
+            expect(await m2.importOlmSessionsFromBackup(sessions, "passphrase")).toStrictEqual({
+                imported: 0,
+                skipped: 1,
+            });
+
+            await m2.updateTrackedUsers([other_user]);
+            await m2.markRequestAsSent("foo", RequestType.KeysQuery, keysQueryResponse);
+
+            // Looking the sessions up caches the lack of sessions with the device.
+            expect(await m2.getMissingSessions([other_user])).toBeInstanceOf(KeysClaimRequest);
+
+            expect(await m2.importOlmSessionsFromBackup(sessions, "passphrase")).toStrictEqual({
+                imported: 1,
+                skipped: 0,
+            });
+
+            // The imported session is used right away.
+            expect(await m2.getMissingSessions([other_user])).toBeNull();
+        });
+
+        test("rejects the Olm sessions of another account", async () => {
+            const sessions = await m.exportOlmSessionsForBackup("passphrase");
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+
+            await m2.updateTrackedUsers([new UserId("@example:localhost")]);
+            await m2.markRequestAsSent("foo", RequestType.KeysQuery, keysQueryResponse);
+            await expect(m2.importOlmSessionsFromBackup(sessions, "passphrase")).rejects.toThrow(
+                "The Olm sessions have been exported from another account",
+            );
+        });
+
+        test("skips the Olm sessions that are already known when importing them", async () => {
+            const sessions = await m.exportOlmSessionsForBackup("passphrase");
+
+            expect(await m.importOlmSessionsFromBackup(sessions, "passphrase")).toStrictEqual({
+                imported: 0,
+                skipped: 1,
+            });
+            await expect(m.importOlmSessionsFromBackup(sessions, "wrong passphrase")).rejects.toThrow();
+        });
+
+        test("can get devices by their Ed25519 key", async () => {
+            const devices = await m.getDevicesByEd25519Key("NayrMQ33ObqMRqz6R9GosmHdT6HQ6b/RX/3QlZ2yiec");
+
+            expect(devices).toHaveLength(1);
+            expect(devices[0]).toBeInstanceOf(Device);
+            expect(devices[0].userId.toString()).toStrictEqual("@example:localhost");
+            expect(devices[0].deviceId.toString()).toStrictEqual("AFGUOBTZWM");
+            expect(devices[0].displayName).toStrictEqual("rust-sdk");
+            expect(devices[0].ed25519Key.toBase64()).toStrictEqual("NayrMQ33ObqMRqz6R9GosmHdT6HQ6b/RX/3QlZ2yiec");
+            expect(devices[0].curve25519Key.toBase64()).toStrictEqual("boYjDpaC+7NkECQEeMh5dC+I1+AfriX0VXG2UV7EUQo");
+            expect(devices[0].localTrustState).toStrictEqual(LocalTrust.Unset);
+            expect(devices[0].isVerified()).toStrictEqual(false);
+
+            expect(await m.getDevicesByEd25519Key("kQXOuy639Yt47mvNTdrIluoC6DMvfbZLYbxAmwiDyhI")).toStrictEqual([]);
+            await expect(m.getDevicesByEd25519Key("invalid")).rejects.toThrow();
+        });
+
+        test("can get devices by their Curve25519 key", async () => {
+            const devices = await m.getDevicesByCurve25519Key("boYjDpaC+7NkECQEeMh5dC+I1+AfriX0VXG2UV7EUQo");
+
+            expect(devices).toHaveLength(1);
+            expect(devices[0]).toBeInstanceOf(Device);
+            expect(devices[0].deviceId.toString()).toStrictEqual("AFGUOBTZWM");
+
+            expect(await m.getDevicesByCurve25519Key("9IGouMnkB6c6HOd4xUsNv4i3Dulb4IS96TzDordzOws")).toStrictEqual([]);
+            await expect(m.getDevicesByCurve25519Key("invalid")).rejects.toThrow();
+        });
+
+        test("can get a device", async () => {
+            const other_user = new UserId("@example:localhost");
+            const device = await m.getDevice(other_user, new DeviceId("AFGUOBTZWM"));
+
+            expect(device).toBeInstanceOf(Device);
+            expect(device.userId.toString()).toStrictEqual("@example:localhost");
+            expect(device.deviceId.toString()).toStrictEqual("AFGUOBTZWM");
+            expect(JSON.parse(device.keys)).toStrictEqual({
+                "curve25519:AFGUOBTZWM": "boYjDpaC+7NkECQEeMh5dC+I1+AfriX0VXG2UV7EUQo",
+                "ed25519:AFGUOBTZWM": "NayrMQ33ObqMRqz6R9GosmHdT6HQ6b/RX/3QlZ2yiec",
+            });
+            expect(device.algorithms).toStrictEqual(["m.olm.v1.curve25519-aes-sha2", "m.megolm.v1.aes-sha2"]);
+            expect(device.isVerified()).toStrictEqual(false);
+
+            expect(await m.getDevice(other_user, new DeviceId("unknown"))).toBeNull();
+        });
+
+        test("can get the devices of a user", async () => {
+            const other_user = new UserId("@example:localhost");
+            const userDevices = await m.getUserDevices(other_user);
+
+            expect(userDevices).toBeInstanceOf(UserDevices);
+            expect(userDevices.devices()).toHaveLength(1);
+            expect(userDevices.get(new DeviceId("AFGUOBTZWM"))).toBeInstanceOf(Device);
+            expect(userDevices.get(new DeviceId("unknown"))).toBeNull();
+            expect(userDevices.isAnyVerified()).toStrictEqual(false);
+            expect(userDevices.isAnyUnverified()).toStrictEqual(true);
+
+            const unverifiedDevices = userDevices.unverifiedDevices();
+            expect(unverifiedDevices).toHaveLength(1);
+            expect(unverifiedDevices[0].deviceId.toString()).toStrictEqual("AFGUOBTZWM");
+            expect(userDevices.blacklistedDevices()).toStrictEqual([]);
+
+            expect((await m.getUserDevices(new UserId("@unknown:localhost"))).devices()).toStrictEqual([]);
+        });
+
+        test("can pin the identity of a user", async () => {
+            const other_user = new UserId("@example:localhost");
+            const identity = await m.getIdentity(other_user);
+
+            expect(identity).toBeInstanceOf(UserIdentity);
+            expect(identity.userId.toString()).toStrictEqual(other_user.toString());
+            expect(identity.isVerified()).toStrictEqual(false);
+            expect(identity.identityNeedsUserApproval()).toStrictEqual(false);
+            expect(Object.values(JSON.parse(identity.masterKey).keys)).toStrictEqual([
+                "n2lpJGx0LiKnuNE1IucZP3QExrD4SeRP0veBHPe3XUU",
+            ]);
+            expect(JSON.parse(identity.selfSigningKey).usage).toStrictEqual(["self_signing"]);
+            expect(await identity.pinCurrentIdentity()).toStrictEqual(undefined);
+
+            // We don't have a user-signing key to verify the identity with.
+            await expect(identity.verify()).rejects.toThrow();
+
+            expect(await m.getIdentity(new UserId("@unknown:localhost"))).toBeNull();
+        });
+
+        test("can resolve an identity conflict", async () => {
+            const other_user = new UserId("@example:localhost");
+
+            await m.resolveIdentityConflict(other_user, IdentityConflictAction.Ignore);
+            await m.resolveIdentityConflict(other_user, IdentityConflictAction.PinNew);
+            expect((await m.getIdentity(other_user)).identityNeedsUserApproval()).toStrictEqual(false);
+
+            await expect(
+                m.resolveIdentityConflict(new UserId("@unknown:localhost"), IdentityConflictAction.PinNew),
+            ).rejects.toThrow();
+        });
+
+        test("cannot verify all the devices of another user", async () => {
+            const userDevices = await m.getUserDevices(new UserId("@example:localhost"));
+
+            await expect(userDevices.verifyAll()).rejects.toThrow();
+        });
+
+        test("can check for pending verification requests", async () => {
+            const other_user = new UserId("@example:localhost");
+
+            expect(await m.hasPendingVerificationRequest(other_user)).toStrictEqual(false);
+
+            const verificationRequest = {
+                sender: other_user.toString(),
+                type: "m.key.verification.request",
+                content: {
+                    from_device: "AFGUOBTZWM",
+                    methods: ["m.sas.v1"],
+                    transaction_id: "pending-verification",
+                    timestamp: Date.now(),
+                },
+            };
+            await m.receiveSyncChanges(JSON.stringify([verificationRequest]), new DeviceLists(), {}, []);
+
+            expect(await m.hasPendingVerificationRequest(other_user)).toStrictEqual(true);
+            expect(await m.hasPendingVerificationRequest(user)).toStrictEqual(false);
+        });
+
+        test("can cancel all verifications with a user", async () => {
+            // Concurrent verification requests with a user are cancelled, hence a new machine.
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            await m2.processInitialDeviceList(keysQueryResponse);
+            const other_user = new UserId("@example:localhost");
+            const verificationRequest = {
+                sender: other_user.toString(),
+                type: "m.key.verification.request",
+                content: {
+                    from_device: "AFGUOBTZWM",
+                    methods: ["m.sas.v1"],
+                    transaction_id: "cancelled-verification",
+                    timestamp: Date.now(),
+                },
+            };
+            await m2.receiveSyncChanges(JSON.stringify([verificationRequest]), new DeviceLists(), {}, []);
+
+            const requests = await m2.cancelAllVerificationsForUser(other_user);
+
+            expect(requests).toHaveLength(1);
+            expect(requests[0]).toBeInstanceOf(ToDeviceRequest);
+            expect(requests[0].eventType).toStrictEqual("m.key.verification.cancel");
+            expect(await m2.hasPendingVerificationRequest(other_user)).toStrictEqual(false);
+            expect(await m2.cancelAllVerificationsForUser(other_user)).toStrictEqual([]);
+        });
+
+        test("can accept a verification request", async () => {
+            // Concurrent verification requests with a user are cancelled, hence a new machine.
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            await m2.processInitialDeviceList(keysQueryResponse);
+            const other_user = new UserId("@example:localhost");
+            const verificationRequest = {
+                sender: other_user.toString(),
+                type: "m.key.verification.request",
+                content: {
+                    from_device: "AFGUOBTZWM",
+                    methods: ["m.sas.v1", "m.qr_code.show.v1"],
+                    transaction_id: "accepted-verification",
+                    timestamp: Date.now(),
+                },
+            };
+            await m2.receiveSyncChanges(JSON.stringify([verificationRequest]), new DeviceLists(), {}, []);
+
+            const accepted = await m2.acceptVerificationRequest("accepted-verification", other_user, [
+                VerificationMethod.SasV1,
+            ]);
+
+            expect(accepted.request).toBeInstanceOf(VerificationRequest);
+            expect(accepted.request.flowId).toStrictEqual("accepted-verification");
+            expect(accepted.request.otherUserId.toString()).toStrictEqual(other_user.toString());
+            expect(accepted.request.otherDeviceId.toString()).toStrictEqual("AFGUOBTZWM");
+            expect(accepted.request.weStarted).toStrictEqual(false);
+            expect(accepted.request.isReady).toStrictEqual(true);
+            expect(accepted.request.theirSupportedMethods).toStrictEqual([
+                VerificationMethod.SasV1,
+                VerificationMethod.QrCodeShowV1,
+            ]);
+            expect(accepted.request.ourSupportedMethods).toStrictEqual([VerificationMethod.SasV1]);
+            expect(accepted.outgoingRequest).toBeInstanceOf(ToDeviceRequest);
+            expect(accepted.outgoingRequest.eventType).toStrictEqual("m.key.verification.ready");
+
+            await expect(
+                m2.acceptVerificationRequest("unknown", other_user, [VerificationMethod.SasV1]),
+            ).rejects.toThrow();
+        });
+
+        test("can get a verification request", async () => {
+            // Concurrent verification requests with a user are cancelled, hence a new machine.
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            await m2.processInitialDeviceList(keysQueryResponse);
+            const other_user = new UserId("@example:localhost");
+            const verificationRequest = {
+                sender: other_user.toString(),
+                type: "m.key.verification.request",
+                content: {
+                    from_device: "AFGUOBTZWM",
+                    methods: ["m.sas.v1"],
+                    transaction_id: "polled-verification",
+                    timestamp: Date.now(),
+                },
+            };
+            await m2.receiveSyncChanges(JSON.stringify([verificationRequest]), new DeviceLists(), {}, []);
+
+            const request = m2.getVerificationRequest(other_user, "polled-verification");
+
+            expect(request).toBeInstanceOf(VerificationRequest);
+            expect(request.flowId).toStrictEqual("polled-verification");
+            expect(request.isReady).toStrictEqual(false);
+
+            const outgoingRequest = request.accept([VerificationMethod.SasV1]);
+
+            expect(outgoingRequest).toBeInstanceOf(ToDeviceRequest);
+            expect(outgoingRequest.eventType).toStrictEqual("m.key.verification.ready");
+            expect(m2.getVerificationRequest(other_user, "polled-verification").isReady).toStrictEqual(true);
+            expect(m2.getVerificationRequest(other_user, "unknown")).toBeNull();
+        });
+
+        test("can request a verification with a device", async () => {
+            const [device] = await m.getDevicesByEd25519Key("NayrMQ33ObqMRqz6R9GosmHdT6HQ6b/RX/3QlZ2yiec");
+
+            const requested = await device.requestVerification([VerificationMethod.SasV1]);
+
+            expect(requested.request).toBeInstanceOf(VerificationRequest);
+            expect(requested.request.otherUserId.toString()).toStrictEqual("@example:localhost");
+            expect(requested.request.weStarted).toStrictEqual(true);
+            expect(requested.outgoingRequest).toBeInstanceOf(ToDeviceRequest);
+            expect(requested.outgoingRequest.eventType).toStrictEqual("m.key.verification.request");
+        });
+
+        test("can request a verification with our own user", async () => {
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            await m2.bootstrapCrossSigning(true);
+
+            const requested = await m2.requestVerification(user, [VerificationMethod.SasV1]);
+
+            expect(requested.request).toBeInstanceOf(VerificationRequest);
+            expect(requested.request.otherUserId.toString()).toStrictEqual(user.toString());
+            expect(requested.request.weStarted).toStrictEqual(true);
+            expect(requested.request.isDone).toStrictEqual(false);
+            expect(requested.request.isCancelled).toStrictEqual(false);
+            expect(requested.outgoingRequest).toBeInstanceOf(ToDeviceRequest);
+            expect(requested.outgoingRequest.eventType).toStrictEqual("m.key.verification.request");
+
+            // We started the verification, so it's up to the other side to accept it.
+            expect(requested.request.accept([VerificationMethod.SasV1])).toBeNull();
+
+            await m2.processInitialDeviceList(keysQueryResponse);
+            await expect(m2.requestVerification(new UserId("@example:localhost"))).rejects.toThrow();
+        });
+
+        test("can request a verification with another user in a room", async () => {
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            await m2.processInitialDeviceList(keysQueryResponse);
+            const other_user = new UserId("@example:localhost");
+
+            const content = JSON.parse(await m2.verificationRequestContent(other_user, [VerificationMethod.SasV1]));
+
+            expect(content.msgtype).toStrictEqual("m.key.verification.request");
+            expect(content.to).toStrictEqual(other_user.toString());
+            expect(content.methods).toStrictEqual(["m.sas.v1"]);
+
+            const request = await m2.requestVerificationInRoom(room, other_user, "$request:localhost", [
+                VerificationMethod.SasV1,
+            ]);
+
+            expect(request).toBeInstanceOf(VerificationRequest);
+            expect(request.flowId).toStrictEqual("$request:localhost");
+            expect(request.roomId.toString()).toStrictEqual(room.toString());
+            expect(request.otherUserId.toString()).toStrictEqual(other_user.toString());
+            expect(request.weStarted).toStrictEqual(true);
+
+            await expect(m2.verificationRequestContent(user)).rejects.toThrow();
+            await expect(m2.requestVerificationInRoom(room, other_user, "not an event ID")).rejects.toThrow();
+        });
+
+        test("can start a SAS verification with a device", async () => {
+            const [device] = await m.getDevicesByEd25519Key("NayrMQ33ObqMRqz6R9GosmHdT6HQ6b/RX/3QlZ2yiec");
+
+            const started = await device.startVerification();
+
+            expect(started.sas).toBeInstanceOf(Sas);
+            expect(started.sas.otherUserId.toString()).toStrictEqual("@example:localhost");
+            expect(started.sas.otherDeviceId.toString()).toStrictEqual("AFGUOBTZWM");
+            expect(started.sas.weStarted).toStrictEqual(true);
+            expect(started.sas.hasBeenAccepted).toStrictEqual(false);
+            expect(started.sas.isDone).toStrictEqual(false);
+            expect(started.outgoingRequest).toBeInstanceOf(ToDeviceRequest);
+            expect(started.outgoingRequest.eventType).toStrictEqual("m.key.verification.start");
+            expect(started.outgoingRequest.id).toStrictEqual(started.outgoingRequest.txnId);
+        });
+
+        test("can drive a SAS verification from the machine", async () => {
+            const other_user = new UserId("@example:localhost");
+            const started = await m.startSasWithDevice(other_user, new DeviceId("AFGUOBTZWM"));
+            const flowId = started.sas.flowId;
+
+            expect(started.sas).toBeInstanceOf(Sas);
+            expect(started.outgoingRequest.eventType).toStrictEqual("m.key.verification.start");
+
+            // We started the verification, so it's up to the other side to accept it.
+            expect(await m.acceptSasVerification(flowId, other_user)).toBeNull();
+            expect(await m.getSasEmojis(flowId, other_user)).toBeNull();
+
+            const confirmed = await m.confirmSasMatch(flowId, other_user);
+            expect(confirmed.outgoingRequests).toStrictEqual([]);
+            expect(confirmed.signatureUploadRequest).toBeUndefined();
+
+            await expect(m.getSasEmojis("unknown", other_user)).rejects.toThrow();
+            await expect(m.startSasWithDevice(other_user, new DeviceId("unknown"))).rejects.toThrow();
+        });
+
+        (qrCodeSupported ? test : test.skip)("can drive a QR code verification from the machine", async () => {
+            // Showing a QR code requires the private part of our master key.
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            await m2.bootstrapCrossSigning(true);
+            await m2.processInitialDeviceList(keysQueryResponse);
+
+            const other_user = new UserId("@example:localhost");
+            const other_device = new DeviceId("AFGUOBTZWM");
+            const verificationRequest = {
+                sender: other_user.toString(),
+                type: "m.key.verification.request",
+                content: {
+                    from_device: other_device.toString(),
+                    methods: ["m.qr_code.scan.v1", "m.reciprocate.v1"],
+                    transaction_id: "qr-verification",
+                    timestamp: Date.now(),
+                },
+            };
+            await m2.receiveSyncChanges(JSON.stringify([verificationRequest]), new DeviceLists(), {}, []);
+            await m2.acceptVerificationRequest("qr-verification", other_user, [
+                VerificationMethod.QrCodeShowV1,
+                VerificationMethod.ReciprocateV1,
+            ]);
+
+            const qr = await m2.startQrVerification(other_user, other_device, "qr-verification");
+
+            expect(qr).toBeInstanceOf(QrCode);
+            expect(qr.flowId).toStrictEqual("qr-verification");
+            expect(qr.otherUserId.toString()).toStrictEqual(other_user.toString());
+            expect(qr.otherDeviceId.toString()).toStrictEqual(other_device.toString());
+            expect(qr.hasBeenScanned).toStrictEqual(false);
+            expect(qr.toBytes()).toBeInstanceOf(Uint8Array);
+            expect(qr.toBase64()).toStrictEqual(Buffer.from(qr.toBytes()).toString("base64").replace(/=+$/, ""));
+
+            // The other side hasn't scanned our QR code yet.
+            expect(await m2.confirmVerification("qr-verification", other_user)).toStrictEqual([]);
+
+            await expect(m2.startQrVerification(other_user, other_device, "unknown")).rejects.toThrow();
+            await expect(
+                m2.startQrVerification(other_user, new DeviceId("unknown"), "qr-verification"),
+            ).rejects.toThrow();
+            await expect(m2.scanQrCode(other_user, other_device, new Uint8Array([1, 2, 3]))).rejects.toThrow();
+            await expect(m2.confirmVerification("unknown", other_user)).rejects.toThrow();
+        });
+
+        test("can get a verification", async () => {
+            const other_user = new UserId("@example:localhost");
+            const started = await m.startSasWithDevice(other_user, new DeviceId("AFGUOBTZWM"));
+
+            const verification = m.getVerification(other_user, started.sas.flowId);
+
+            expect(verification).toBeInstanceOf(Verification);
+            expect(verification.type).toStrictEqual(VerificationType.Sas);
+            expect(verification.type).toStrictEqual("sas");
+            expect(verification.flowId).toStrictEqual(started.sas.flowId);
+            expect(verification.otherUserId.toString()).toStrictEqual(other_user.toString());
+            expect(verification.isDone).toStrictEqual(false);
+            expect(verification.isCancelled).toStrictEqual(false);
+            expect(verification.cancelCode).toBeNull();
+
+            // We started the verification, so it's up to the other side to accept it.
+            expect(verification.accept()).toBeNull();
+
+            const cancelRequest = verification.cancel();
+
+            expect(cancelRequest).toBeInstanceOf(ToDeviceRequest);
+            expect(cancelRequest.eventType).toStrictEqual("m.key.verification.cancel");
+            expect(verification.isCancelled).toStrictEqual(true);
+            expect(verification.cancelCode).toStrictEqual("m.user");
+            expect(verification.cancel()).toBeNull();
+            expect(m.getVerification(other_user, "unknown")).toBeNull();
+        });
+
         test("can share a room key", async () => {
             const other_users = [new UserId("@example:localhost")];
 
@@ -397,6 +1269,66 @@ describe(OlmMachine.name, () => {
             expect(encrypted.session_id).toBeDefined();
         });
 
+        test("can encrypt a batch of events", async () => {
+            const encrypted = await m.batchEncrypt([
+                { roomId: room.toString(), eventType: "m.room.message", content: JSON.stringify({ body: "first" }) },
+                { roomId: room.toString(), eventType: "m.room.message", content: JSON.stringify({ body: "second" }) },
+            ]);
+
+            expect(encrypted).toHaveLength(2);
+
+            const contents = encrypted.map((content) => JSON.parse(content));
+            expect(contents[0].session_id).toStrictEqual(contents[1].session_id);
+            expect(contents[0].ciphertext).not.toStrictEqual(contents[1].ciphertext);
+
+            await expect(
+                m.batchEncrypt([{ roomId: "invalid", eventType: "m.room.message", content: "{}" }]),
+            ).rejects.toThrow();
+        });
+
+        test("can check whether a Megolm session has been shared with a device", async () => {
+            const other_user = new UserId("@example:localhost");
+            const other_device = new DeviceId("AFGUOBTZWM");
+            const shared_room = new RoomId("!shared:localhost");
+
+            const requests = await m.shareRoomKey(shared_room, [other_user], new EncryptionSettings());
+            const { session_id } = JSON.parse(await m.encryptRoomEvent(shared_room, "m.room.message", "{}"));
+
+            const isShared = () => m.isMegolmSessionSharedWith(shared_room, session_id, other_user, other_device);
+
+            expect(await isShared()).toStrictEqual(false);
+
+            for (const request of requests) {
+                await m.markRequestAsSent(request.txnId, request.type, "{}");
+            }
+
+            expect(await isShared()).toStrictEqual(true);
+            expect(
+                await m.isMegolmSessionSharedWith(shared_room, "unknown", other_user, other_device),
+            ).toStrictEqual(false);
+        });
+
+        test("can encrypt an Olm message", async () => {
+            const other_user = new UserId("@example:localhost");
+
+            const content = JSON.parse(
+                await m.encryptOlmMessage(
+                    other_user,
+                    new DeviceId("AFGUOBTZWM"),
+                    "org.example.custom",
+                    JSON.stringify({ hello: "world" }),
+                ),
+            );
+
+            expect(content.algorithm).toStrictEqual("m.olm.v1.curve25519-aes-sha2");
+            expect(content.sender_key).toBeDefined();
+            expect(Object.keys(content.ciphertext)).toStrictEqual(["boYjDpaC+7NkECQEeMh5dC+I1+AfriX0VXG2UV7EUQo"]);
+
+            await expect(
+                m.encryptOlmMessage(other_user, new DeviceId("unknown"), "org.example.custom", "{}"),
+            ).rejects.toThrow();
+        });
+
         test("can decrypt an event", async () => {
             const decrypted = await m.decryptRoomEvent(
                 JSON.stringify({
@@ -425,6 +1357,179 @@ describe(OlmMachine.name, () => {
             expect(decrypted.shieldState(true).color).toStrictEqual(ShieldColor.Red);
             expect(decrypted.shieldState(false).color).toStrictEqual(ShieldColor.Red);
         });
+
+        test("can try to decrypt an event", async () => {
+            const event = JSON.stringify({
+                type: "m.room.encrypted",
+                event_id: "$xxxxx:example.org",
+                origin_server_ts: Date.now(),
+                sender: user.toString(),
+                content: encrypted,
+            });
+
+            const decrypted = await m.tryDecrypt(event, room);
+            expect(decrypted).toBeInstanceOf(DecryptedRoomEvent);
+            expect(JSON.parse(decrypted.event).content.hello).toStrictEqual("world");
+
+            const error = await m.tryDecrypt(event, new RoomId("!unknown:localhost"));
+            expect(error).toBeInstanceOf(DecryptionError);
+            expect(error.event).toStrictEqual(event);
+            expect(error.description).toBeDefined();
+        });
+
+        test("can decrypt a batch of events", async () => {
+            const event = JSON.stringify({
+                type: "m.room.encrypted",
+                event_id: "$xxxxx:example.org",
+                origin_server_ts: Date.now(),
+                sender: user.toString(),
+                content: encrypted,
+            });
+
+            const results = await m.batchDecrypt([
+                { event, roomId: room.toString() },
+                { event, roomId: "!unknown:localhost" },
+                { event, roomId: "invalid" },
+                { event, roomId: room.toString() },
+            ]);
+
+            expect(results).toHaveLength(4);
+            expect(results[0]).toBeInstanceOf(DecryptedRoomEvent);
+            expect(results[1]).toBeInstanceOf(DecryptionError);
+            expect(results[2]).toBeInstanceOf(DecryptionError);
+            expect(results[3]).toBeInstanceOf(DecryptedRoomEvent);
+            expect(JSON.parse(results[3].event).content.hello).toStrictEqual("world");
+        });
+
+        test("can decrypt a Megolm message", async () => {
+            const timestamp = Date.now();
+            const decrypted = JSON.parse(
+                await m.decryptMegolmMessage(encrypted.ciphertext, encrypted.session_id, room, BigInt(timestamp)),
+            );
+
+            expect(decrypted.type).toStrictEqual("m.room.message");
+            expect(decrypted.content.hello).toStrictEqual("world");
+            expect(decrypted.room_id).toStrictEqual(room.toString());
+            expect(decrypted.origin_server_ts).toStrictEqual(timestamp);
+            expect(decrypted.sender).toBeUndefined();
+            expect(decrypted.event_id).toBeUndefined();
+
+            await expect(
+                m.decryptMegolmMessage(encrypted.ciphertext, "unknown", room, BigInt(timestamp)),
+            ).rejects.toThrow();
+            await expect(
+                m.decryptMegolmMessage(encrypted.ciphertext, encrypted.session_id, room, BigInt(-timestamp)),
+            ).rejects.toThrow();
+            await expect(
+                m.decryptMegolmMessage(encrypted.ciphertext, encrypted.session_id, room, 2n ** 64n),
+            ).rejects.toThrow();
+        });
+
+        test("can count the decrypted messages", async () => {
+            const count = await m.getReceiveCount();
+
+            expect(count.olmMessages).toStrictEqual(0);
+            expect(count.megolmMessages).toBeGreaterThan(0);
+        });
+
+        test("can count the sent messages", async () => {
+            const other_user = new UserId("@example:localhost");
+            const counted_room = new RoomId("!counted:localhost");
+            const before = await m.getSendCount();
+
+            const requests = await m.shareRoomKey(counted_room, [other_user], new EncryptionSettings());
+            for (const request of requests) {
+                await m.markRequestAsSent(request.txnId, request.type, "{}");
+            }
+            await m.encryptRoomEvent(counted_room, "m.room.message", "{}");
+
+            const after = await m.getSendCount();
+            expect(requests).toHaveLength(1);
+            expect(after.toDeviceMessages).toStrictEqual(before.toDeviceMessages + 1);
+            expect(after.roomMessages).toStrictEqual(before.roomMessages + 1);
+        });
+
+        test("can blacklist a device", async () => {
+            const other_user = new UserId("@example:localhost");
+            const other_device = new DeviceId("AFGUOBTZWM");
+            const [device] = await m.getDevicesByEd25519Key("NayrMQ33ObqMRqz6R9GosmHdT6HQ6b/RX/3QlZ2yiec");
+
+            expect(device.isBlacklisted).toStrictEqual(false);
+
+            await device.blacklist();
+
+            expect(device.isBlacklisted).toStrictEqual(true);
+            expect(await m.localTrustForDevice(other_user, other_device)).toStrictEqual(LocalTrust.BlackListed);
+
+            const blacklistedDevices = (await m.getUserDevices(other_user)).blacklistedDevices();
+            expect(blacklistedDevices).toHaveLength(1);
+            expect(blacklistedDevices[0].deviceId.toString()).toStrictEqual(other_device.toString());
+        });
+
+        test("can reject a new identity", async () => {
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            const other_user = new UserId("@example:localhost");
+            await m2.processInitialDeviceList(keysQueryResponse);
+
+            await m2.resolveIdentityConflict(other_user, IdentityConflictAction.Reject);
+
+            expect(await m2.localTrustForDevice(other_user, new DeviceId("AFGUOBTZWM"))).toStrictEqual(
+                LocalTrust.BlackListed,
+            );
+        });
+
+        test("can export the verification state", async () => {
+            const exported = await m.exportVerificationState("passphrase");
+
+            // The export is encrypted, so the trust records don't leak.
+            expect(Object.keys(JSON.parse(exported)).sort()).toStrictEqual(["cipher", "payload"]);
+            expect(exported).not.toContain("AFGUOBTZWM");
+
+            // Exports which can't be authenticated are rejected as a whole.
+            const tampered = JSON.parse(exported);
+            tampered.payload = Buffer.from(JSON.stringify({ devices: [], identities: [] }))
+                .toString("base64")
+                .replace(/=+$/, "");
+            await expect(m.importVerificationState(JSON.stringify(tampered), "passphrase")).rejects.toThrow();
+            await expect(m.importVerificationState("{}", "passphrase")).rejects.toThrow();
+        });
+
+        test("can import the verification state", async () => {
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            const other_user = new UserId("@example:localhost");
+            const other_device = new DeviceId("AFGUOBTZWM");
+            await m2.processInitialDeviceList(keysQueryResponse);
+
+            const exported = await m.exportVerificationState("passphrase");
+
+            await expect(m2.importVerificationState(exported, "wrong passphrase")).rejects.toThrow();
+            expect(await m2.localTrustForDevice(other_user, other_device)).toStrictEqual(LocalTrust.Unset);
+
+            await m2.importVerificationState(exported, "passphrase");
+            expect(await m2.localTrustForDevice(other_user, other_device)).toStrictEqual(LocalTrust.BlackListed);
+        });
+
+        test("can export and import the room keys", async () => {
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            const exported = await m.exportRoomKeys("passphrase", 1000);
+
+            expect(exported).toMatch(/^-----BEGIN MEGOLM SESSION DATA-----/);
+
+            const progress = [];
+            const result = await m2.importRoomKeys(exported, "passphrase", (processed, total) =>
+                progress.push([processed, total]),
+            );
+
+            expect(result.total).toBeGreaterThan(0);
+            expect(result.imported).toStrictEqual(result.total);
+            expect(JSON.parse(result.keys)[room.toString()]).toContain(encrypted.session_id);
+
+            // Let the progress callbacks run.
+            await new Promise((resolve) => setImmediate(resolve));
+            expect(progress).toContainEqual([0, result.total]);
+
+            await expect(m2.importRoomKeys(exported, "wrong passphrase")).rejects.toThrow();
+        });
     });
 
     test("can update tracked users", async () => {
@@ -433,6 +1538,136 @@ describe(OlmMachine.name, () => {
         expect(await m.updateTrackedUsers([user])).toStrictEqual(undefined);
     });
 
+    test("can ensure devices are tracked", async () => {
+        const m = await machine();
+        const bob = new UserId("@bob:example.org");
+
+        const keysQueries = await m.ensureDevicesAreTracked([bob]);
+
+        expect(keysQueries).toHaveLength(1);
+        expect(keysQueries[0]).toBeInstanceOf(KeysQueryRequest);
+        expect(Object.keys(JSON.parse(keysQueries[0].body).device_keys)).toContain(bob.toString());
+    });
+
+    test("can process room member events", async () => {
+        const m = await machine();
+        const bob = "@bob:example.org";
+        const memberEvent = (membership) =>
+            JSON.stringify({
+                type: "m.room.member",
+                event_id: `$${membership}`,
+                sender: bob,
+                state_key: bob,
+                origin_server_ts: 1,
+                content: { membership },
+            });
+
+        expect(await m.processRoomMemberEvent(memberEvent("join"), room)).toStrictEqual(undefined);
+
+        const keysQuery = (await m.outgoingRequests()).find((request) => request instanceof KeysQueryRequest);
+        expect(Object.keys(JSON.parse(keysQuery.body).device_keys)).toContain(bob);
+
+        expect(await m.processRoomMemberEvent(memberEvent("leave"), room)).toStrictEqual(undefined);
+        await expect(m.processRoomMemberEvent("{}", room)).rejects.toThrow();
+    });
+
+    test("can sign an own device", async () => {
+        const m = await machine();
+        await m.updateTrackedUsers([user]);
+
+        const [ownDevice] = await m.getDevicesByEd25519Key(m.identityKeys.ed25519.toBase64());
+        await expect(ownDevice.signDevice()).rejects.toThrow();
+
+        await m.bootstrapCrossSigning(true);
+
+        const request = await ownDevice.signDevice();
+        expect(request).toBeInstanceOf(SignatureUploadRequest);
+        expect(JSON.parse(request.body).signed_keys[user.toString()][device.toString()]).toBeDefined();
+    });
+
+    test("can verify all the devices of a user", async () => {
+        const m = await machine();
+        await m.updateTrackedUsers([user]);
+        await m.bootstrapCrossSigning(true);
+
+        // Bootstrapping cross-signing signs our own device.
+        expect(await (await m.getUserDevices(user)).verifyAll()).toBeNull();
+    });
+
+    test("can sign a device with the own identity", async () => {
+        const m = await machine();
+        await m.bootstrapCrossSigning(true);
+
+        const identity = await m.getIdentity(user);
+        expect(identity).toBeInstanceOf(OwnUserIdentity);
+        expect(identity.isVerified()).toStrictEqual(true);
+        expect(JSON.parse(identity.masterKey).usage).toStrictEqual(["master"]);
+        expect(JSON.parse(identity.selfSigningKey).usage).toStrictEqual(["self_signing"]);
+        expect(JSON.parse(identity.userSigningKey).usage).toStrictEqual(["user_signing"]);
+        expect(await identity.verify()).toBeInstanceOf(SignatureUploadRequest);
+
+        expect(await identity.signDevice(user, device)).toBeInstanceOf(SignatureUploadRequest);
+        expect(await identity.signDevice(user, new DeviceId("unknown"))).toBeNull();
+        await expect(identity.signDevice(new UserId("@bob:example.org"), device)).rejects.toThrow();
+    });
+
+    test("can ensure Olm sessions for users", async () => {
+        const m = await machine();
+
+        expect(await m.ensureOlmSessionsForUsers([new UserId("@bob:example.org")])).toStrictEqual([]);
+    });
+
+    test("can read the local trust state of a device", async () => {
+        const m = await machine();
+
+        expect(await m.localTrustForDevice(user, device)).toStrictEqual(LocalTrust.Verified);
+        expect(await m.localTrustForDevice(user, new DeviceId("unknown"))).toBeNull();
+    });
+
+    test("can read the Curve25519 key of a device", async () => {
+        const m = await machine();
+
+        expect(await m.getDeviceCurve25519Key(user, device)).toStrictEqual(m.identityKeys.curve25519.toBase64());
+        expect(await m.getDeviceCurve25519Key(user, new DeviceId("unknown"))).toBeNull();
+    });
+
+    test("can read the Ed25519 key of a device", async () => {
+        const m = await machine();
+
+        expect(await m.getDeviceEd25519Key(user, device)).toStrictEqual(m.identityKeys.ed25519.toBase64());
+        expect(await m.getDeviceEd25519Key(user, new DeviceId("unknown"))).toBeNull();
+    });
+
+    test("can hold temporary secrets", async () => {
+        const m = await machine();
+
+        expect(m.getTemporarySecret("m.cross_signing.master")).toBeNull();
+
+        await m.addTemporarySecret("m.cross_signing.master", "foo");
+        await m.addTemporarySecret("m.cross_signing.self_signing", "bar");
+        expect(m.getTemporarySecret("m.cross_signing.master")).toStrictEqual("foo");
+
+        await m.addTemporarySecret("m.cross_signing.master", "baz");
+        expect(m.getTemporarySecret("m.cross_signing.master")).toStrictEqual("baz");
+
+        m.clearTemporarySecrets();
+        expect(m.getTemporarySecret("m.cross_signing.master")).toBeNull();
+        expect(m.getTemporarySecret("m.cross_signing.self_signing")).toBeNull();
+    });
+
+    test("can store custom account data", async () => {
+        const m = await machine();
+
+        expect(await m.getCustomAccountData("org.example.shared_history")).toBeNull();
+
+        await m.setCustomAccountData("org.example.shared_history", JSON.stringify({ rooms: [room.toString()] }));
+        expect(JSON.parse(await m.getCustomAccountData("org.example.shared_history"))).toStrictEqual({
+            rooms: [room.toString()],
+        });
+
+        await expect(m.setCustomAccountData("org.example.shared_history", "{invalid")).rejects.toThrow();
+    });
+
     test("can read cross-signing status", async () => {
         const m = await machine();
         const crossSigningStatus = await m.crossSigningStatus();
@@ -443,6 +1678,23 @@ describe(OlmMachine.name, () => {
         expect(crossSigningStatus.hasUserSigning).toStrictEqual(false);
     });
 
+    test("can bootstrap cross-signing", async () => {
+        const m = await machine();
+        const requests = await m.bootstrapCrossSigning(true);
+
+        expect(requests.uploadKeysRequest).toBeInstanceOf(KeysUploadRequest);
+        expect(requests.uploadSigningKeysRequest).toBeInstanceOf(SigningKeysUploadRequest);
+        expect(Object.keys(JSON.parse(requests.uploadSigningKeysRequest.body))).toStrictEqual([
+            "master_key",
+            "self_signing_key",
+            "user_signing_key",
+        ]);
+        expect(requests.uploadSignaturesRequest).toBeInstanceOf(SignatureUploadRequest);
+
+        const crossSigningStatus = await m.crossSigningStatus();
+        expect(crossSigningStatus.hasMaster).toStrictEqual(true);
+    });
+
     test("can sign a message", async () => {
         const m = await machine();
         const signatures = await m.sign("foo");
@@ -537,6 +1789,34 @@ describe(OlmMachine.name, () => {
             expect(state.deviceState).toStrictEqual(SignatureState.ValidAndTrusted);
             expect(state.userState).toStrictEqual(SignatureState.ValidAndTrusted);
         });
+
+        test("accepts signatures from generateBackupSignature", async () => {
+            let m = await machine();
+            await m.bootstrapCrossSigning(true);
+
+            let keyBackupKey = BackupDecryptionKey.createRandomKey();
+
+            let authData = JSON.parse(
+                await m.generateBackupSignature(
+                    JSON.stringify({
+                        public_key: keyBackupKey.megolmV1PublicKey.publicKeyBase64,
+                    }),
+                ),
+            );
+
+            expect(authData.public_key).toStrictEqual(keyBackupKey.megolmV1PublicKey.publicKeyBase64);
+            expect(Object.keys(authData.signatures[user.toString()])).toHaveLength(2);
+
+            let backupData = {
+                algorithm: keyBackupKey.megolmV1PublicKey.algorithm,
+                auth_data: authData,
+            };
+
+            const state = await m.verifyBackup(JSON.stringify(backupData));
+
+            expect(state.deviceState).toStrictEqual(SignatureState.ValidAndTrusted);
+            expect(state.userState).toStrictEqual(SignatureState.ValidAndTrusted);
+        });
     });
 
     describe("key backup", () => {
@@ -562,6 +1842,7 @@ describe(OlmMachine.name, () => {
             let outgoing = await m.backupRoomKeys();
 
             expect(outgoing.id).toBeDefined();
+            expect(outgoing.version).toStrictEqual("1");
             expect(outgoing.body).toBeDefined();
             expect(outgoing.type).toStrictEqual(RequestType.KeysBackup);
 
@@ -585,6 +1866,147 @@ describe(OlmMachine.name, () => {
             expect(newCounts.backedUp).toStrictEqual(1);
         });
 
+        test("can enable a backup from its information", async () => {
+            const m = await machine();
+            const keyBackupKey = BackupDecryptionKey.createRandomKey();
+            const authData = JSON.stringify({ public_key: keyBackupKey.megolmV1PublicKey.publicKeyBase64 });
+
+            await expect(m.enableBackup("org.example.unknown", "1", authData)).rejects.toThrow();
+            expect(await m.isBackupEnabled()).toStrictEqual(false);
+
+            await m.enableBackup("m.megolm_backup.v1.curve25519-aes-sha2", "2", authData);
+            expect(await m.isBackupEnabled()).toStrictEqual(true);
+
+            await m.shareRoomKey(room, [new UserId("@bob:example.org")], new EncryptionSettings());
+            expect((await m.backupRoomKeys()).version).toStrictEqual("2");
+
+            await m.disableBackup();
+            expect(await m.isBackupEnabled()).toStrictEqual(false);
+        });
+
+        test("can mark a room key as uploaded", async () => {
+            const m = await machine();
+
+            await m.shareRoomKey(room, [new UserId("@bob:example.org")], new EncryptionSettings());
+            const sessionId = JSON.parse(await m.encryptRoomEvent(room, "m.room.message", "{}")).session_id;
+
+            await expect(m.markBackupKeyAsUploaded(room, sessionId)).rejects.toThrow();
+
+            await m.enableBackupV1(BackupDecryptionKey.createRandomKey().megolmV1PublicKey.publicKeyBase64, "1");
+            await m.markBackupKeyAsUploaded(room, sessionId);
+
+            expect((await m.roomKeyCounts()).backedUp).toStrictEqual(1);
+            expect(await m.backupRoomKeys()).toBeNull();
+        });
+
+        test("can restore the room keys of a backup", async () => {
+            const m = await machine();
+            const backupKey = BackupDecryptionKey.createRandomKey();
+
+            await m.shareRoomKey(room, [new UserId("@bob:example.org")], new EncryptionSettings());
+            await m.enableBackupV1(backupKey.megolmV1PublicKey.publicKeyBase64, "1");
+
+            const body = JSON.parse((await m.backupRoomKeys()).body);
+            const [sessionId] = Object.keys(body.rooms[room.toString()].sessions);
+
+            // A room key that cannot be decrypted is skipped.
+            body.rooms["!other:matrix.org"] = {
+                sessions: { unknown: { ...body.rooms[room.toString()].sessions[sessionId], session_data: {} } },
+            };
+
+            const m2 = await machine(user, new DeviceId("OTHERDEVICE"));
+            const result = await m2.restoreBackup(
+                BackupDecryptionKey.fromBase64(backupKey.toBase64()),
+                "1",
+                JSON.stringify(body),
+            );
+
+            expect(result.total).toStrictEqual(1);
+            expect(result.imported).toStrictEqual(1);
+            expect(JSON.parse(result.keys)).toStrictEqual({ [room.toString()]: [sessionId] });
+
+            await expect(m2.restoreBackup(backupKey, "1", "{}")).rejects.toThrow();
+            await expect(m2.restoreBackup(backupKey, "1", "[]")).rejects.toThrow("The body has no `rooms` field");
+            await expect(m2.restoreBackup(backupKey, "1", "1")).rejects.toThrow("The body has no `rooms` field");
+        });
+
+        test("can count the backed up room keys", async () => {
+            let m = await machine();
+
+            await m.shareRoomKey(room, [new UserId("@bob:example.org")], new EncryptionSettings());
+            expect(await m.getBackedUpSessionCount()).toStrictEqual(0);
+
+            await m.enableBackupV1(BackupDecryptionKey.createRandomKey().megolmV1PublicKey.publicKeyBase64, "1");
+
+            let outgoing = await m.backupRoomKeys();
+            await m.markRequestAsSent(outgoing.id, outgoing.type, '{"etag":"1","count":1}');
+
+            expect(await m.getBackedUpSessionCount()).toStrictEqual(1);
+            expect(await m.getUnbackedUpSessionCount()).toStrictEqual(0);
+        });
+
+        test("can count all the room keys", async () => {
+            let m = await machine();
+
+            expect(await m.getTotalSessionCount()).toStrictEqual(0);
+
+            await m.shareRoomKey(room, [new UserId("@bob:example.org")], new EncryptionSettings());
+
+            expect(await m.getTotalSessionCount()).toStrictEqual(1);
+        });
+
+        test("can count the room keys not backed up yet", async () => {
+            let m = await machine();
+
+            expect(await m.getUnbackedUpSessionCount()).toStrictEqual(0);
+
+            await m.shareRoomKey(room, [new UserId("@bob:example.org")], new EncryptionSettings());
+
+            expect(await m.getUnbackedUpSessionCount()).toStrictEqual(1);
+        });
+
+        test("can switch to another backup version", async () => {
+            let m = await machine();
+
+            await expect(m.prepareBackupForVersion("2")).rejects.toThrow();
+
+            let keyBackupKey = BackupDecryptionKey.createRandomKey();
+
+            await m.saveBackupDecryptionKey(keyBackupKey, "1");
+            await m.enableBackupV1(keyBackupKey.megolmV1PublicKey.publicKeyBase64, "1");
+            await m.prepareBackupForVersion("2");
+
+            expect(await m.isBackupEnabled()).toStrictEqual(true);
+            expect((await m.getBackupKeys()).backupVersion).toStrictEqual("2");
+        });
+
+        test("can switch to another backup version with pending room keys", async () => {
+            const temp_directory = await fs.mkdtemp(path.join(os.tmpdir(), "matrix-sdk-crypto--"));
+            const m = await OlmMachine.initialize(user, device, temp_directory, null, StoreType.Sqlite);
+            const keyBackupKey = BackupDecryptionKey.createRandomKey();
+            const otherRoom = new RoomId("!other:matrix.org");
+
+            await m.saveBackupDecryptionKey(keyBackupKey, "1");
+            await m.enableBackupV1(keyBackupKey.megolmV1PublicKey.publicKeyBase64, "1");
+
+            // A room key backed up to the old version.
+            await m.shareRoomKey(room, [new UserId("@bob:example.org")], new EncryptionSettings());
+            const sent = await m.backupRoomKeys();
+            await m.markRequestAsSent(sent.id, sent.type, '{"etag":"1","count":1}');
+
+            // A room key whose backup request is still pending.
+            await m.shareRoomKey(otherRoom, [new UserId("@bob:example.org")], new EncryptionSettings());
+            expect((await m.backupRoomKeys()).version).toStrictEqual("1");
+
+            await m.prepareBackupForVersion("2");
+
+            const outgoing = await m.backupRoomKeys();
+            expect(outgoing.version).toStrictEqual("2");
+            expect(Object.keys(JSON.parse(outgoing.body).rooms).sort()).toStrictEqual(
+                [otherRoom.toString(), room.toString()].sort(),
+            );
+        });
+
         test("can save and get private key", async () => {
             let m = await machine();
 
@@ -597,5 +2019,37 @@ describe(OlmMachine.name, () => {
             expect(savedKey.decryptionKeyBase64).toStrictEqual(keyBackupKey.toBase64());
             expect(savedKey.backupVersion).toStrictEqual("3");
         });
+
+        test("can get the current backup key version", async () => {
+            let m = await machine();
+
+            expect(await m.currentBackupKeyVersion()).toBeNull();
+
+            let keyBackupKey = BackupDecryptionKey.createRandomKey();
+
+            await m.saveBackupDecryptionKey(keyBackupKey, "3");
+
+            let current = await m.currentBackupKeyVersion();
+
+            expect(current.version).toStrictEqual("3");
+            expect(current.publicKey).toStrictEqual(keyBackupKey.megolmV1PublicKey.publicKeyBase64);
+        });
+
+        test("can verify the public key of a server-side backup", async () => {
+            let m = await machine();
+
+            let keyBackupKey = BackupDecryptionKey.createRandomKey();
+            let authData = JSON.stringify({ public_key: keyBackupKey.megolmV1PublicKey.publicKeyBase64 });
+            let otherAuthData = JSON.stringify({
+                public_key: BackupDecryptionKey.createRandomKey().megolmV1PublicKey.publicKeyBase64,
+            });
+
+            expect(await m.verifyKeyBackupPublicKey(authData)).toStrictEqual(false);
+
+            await m.saveBackupDecryptionKey(keyBackupKey, "3");
+
+            expect(await m.verifyKeyBackupPublicKey(authData)).toStrictEqual(true);
+            expect(await m.verifyKeyBackupPublicKey(otherAuthData)).toStrictEqual(false);
+        });
     });
 });